@@ -0,0 +1,795 @@
+// Assembler: builds a ROM from source using the Cowgod mnemonics the
+// disassembler prints.
+//
+//     ; comments run to the end of the line
+//     SPEED equ 3            ; constants, `SPEED = 3` works too
+//     start:                 ; labels
+//         LD I, ball
+//         DRW V0, V1, 4
+//         ADD V0, SPEED * 2
+//         JP start
+//     ball:
+//         db %01100000, 0xF0, #F0, "AB"   ; data bytes and text
+//         dw start + 2                     ; data words, big endian
+//     include "more.asm"     ; another file, relative to this one
+//     org 0x300              ; continue at an address
+//
+// Numbers are decimal, hexadecimal after `#` or `0x`, binary after `%` or
+// `0b`. Expressions have the C operators `+ - * / % << >> & | ^ ~` and
+// parentheses, `$` is the address of the current line. Constants and labels
+// can be used before they are defined.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::variant::Variant;
+
+// Nested includes deeper than this are assumed to include each other.
+const MAX_INCLUDE_DEPTH: usize = 16;
+// Constants referring to constants deeper than this are assumed to be
+// circular.
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    // Line number starting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+// The assembled program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    // Every label and its address, in address order.
+    pub symbols: Vec<(String, u32)>,
+}
+
+impl Assembly {
+    // One `ADDR name` line per label, for debuggers and the disassembler.
+    pub fn symbol_file(&self) -> String {
+        self.symbols.iter().map(|(name, addr)| format!("{:04X} {}\n", addr, name)).collect()
+    }
+
+    // Write the ROM to `output`, by default next to `source` with a .ch8
+    // extension, and the symbol file if asked for.
+    pub fn write(&self, source: &str, output: Option<&str>, symbols: Option<&str>) -> Result<(), String> {
+        let output = output.map_or_else(|| Path::new(source).with_extension("ch8").to_string_lossy().into_owned(), str::to_string);
+        fs::write(&output, &self.rom).map_err(|error| format!("Problem writing {}: {}", output, error))?;
+        if let Some(symbols) = symbols {
+            fs::write(symbols, self.symbol_file()).map_err(|error| format!("Problem writing {}: {}", symbols, error))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(i64),
+    Symbol(String),
+    // `$`, the address of the line.
+    Here,
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum DataItem {
+    Value(Expr),
+    Text(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    Label(String),
+    Constant(String, Expr),
+    Org(Expr),
+    // Bytes or words.
+    Data(u32, Vec<DataItem>),
+    Instruction(String, Vec<String>),
+}
+
+struct Statement {
+    file: String,
+    line: usize,
+    kind: Kind,
+}
+
+impl Statement {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message }
+    }
+}
+
+// Split at commas that aren't inside quotes or parentheses.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                parts.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !parts.is_empty() {
+        parts.push(current.trim().to_string());
+    }
+    parts
+}
+
+// The line without its comment.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'),
+        _ => false,
+    }
+}
+
+fn parse_string(text: &str) -> Result<Vec<u8>, String> {
+    let inner = text.strip_prefix('"').and_then(|text| text.strip_suffix('"')).ok_or(format!("unterminated string {}", text))?;
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' { chars.next().ok_or("string ends with a backslash")? } else { c };
+        if !c.is_ascii() {
+            return Err(format!("only ASCII is allowed in strings, found {}", c));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(bytes)
+}
+
+// Recursive descent over the operators, loosest binding first.
+struct ExprParser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+const BINARY_LEVELS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+impl<'a> ExprParser<'a> {
+    fn skip_spaces(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.text[self.pos..].starts_with(token.as_bytes()) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for operator in BINARY_LEVELS[level] {
+                if self.eat(operator) {
+                    let right = self.binary(level + 1)?;
+                    // Shifts are stored by their first character
+                    left = Expr::Binary(operator.chars().next().unwrap(), Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("~") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("+") {
+            return self.unary();
+        }
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            if !self.eat(")") {
+                return Err("missing )".to_string());
+            }
+            return Ok(expr);
+        }
+        self.skip_spaces();
+        let start = self.pos;
+        // Prefixes only count at the start, `a%b` is a remainder
+        if self.pos < self.text.len() && b"#%$".contains(&self.text[self.pos]) {
+            self.pos += 1;
+        }
+        while self.pos < self.text.len() && (self.text[self.pos].is_ascii_alphanumeric() || b"_.".contains(&self.text[self.pos])) {
+            self.pos += 1;
+        }
+        let token = std::str::from_utf8(&self.text[start..self.pos]).unwrap_or("");
+        parse_atom(token)
+    }
+}
+
+fn parse_atom(token: &str) -> Result<Expr, String> {
+    let number = |digits: &str, radix: u32| i64::from_str_radix(digits, radix).map(Expr::Number).map_err(|_| format!("invalid number {}", token));
+    if token.is_empty() {
+        return Err("missing value".to_string());
+    }
+    if token == "$" {
+        return Ok(Expr::Here);
+    }
+    if let Some(digits) = token.strip_prefix('#').or_else(|| token.strip_prefix("0x")).or_else(|| token.strip_prefix("0X")) {
+        return number(digits, 16);
+    }
+    if let Some(digits) = token.strip_prefix('%').or_else(|| token.strip_prefix("0b")).or_else(|| token.strip_prefix("0B")) {
+        return number(digits, 2);
+    }
+    if token.as_bytes()[0].is_ascii_digit() {
+        return number(token, 10);
+    }
+    if is_identifier(token) {
+        return Ok(Expr::Symbol(token.to_string()));
+    }
+    Err(format!("invalid value {}", token))
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser { text: text.as_bytes(), pos: 0 };
+    let expr = parser.binary(0)?;
+    parser.skip_spaces();
+    if parser.pos != text.len() {
+        return Err(format!("unexpected {} in expression", &text[parser.pos..]));
+    }
+    Ok(expr)
+}
+
+// Reads included files, given the including file and the name in quotes.
+pub type Loader<'a> = dyn Fn(&str, &str) -> io::Result<(String, String)> + 'a;
+
+// Include paths are relative to the directory of the including file.
+fn load_relative(from: &str, name: &str) -> io::Result<(String, String)> {
+    let path = Path::new(from).parent().unwrap_or_else(|| Path::new("")).join(name);
+    let path = path.to_string_lossy().into_owned();
+    let source = fs::read_to_string(&path)?;
+    Ok((path, source))
+}
+
+fn parse_source(file: &str, source: &str, loader: &Loader, depth: usize, statements: &mut Vec<Statement>) -> Result<(), AsmError> {
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| AsmError { file: file.to_string(), line: index + 1, message };
+        let mut push = |kind: Kind| statements.push(Statement { file: file.to_string(), line: index + 1, kind });
+        let mut text = strip_comment(line).trim();
+
+        // Labels, possibly followed by a statement
+        if let Some(colon) = text.find(':') {
+            let name = text[..colon].trim();
+            if is_identifier(name) {
+                push(Kind::Label(name.to_string()));
+                text = text[colon + 1..].trim();
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (word, rest) = match text.find(char::is_whitespace) {
+            Some(index) => (&text[..index], text[index..].trim()),
+            None => (text, ""),
+        };
+        // Constants: NAME equ EXPR or NAME = EXPR
+        let definition = if let Some(value) = rest.strip_prefix('=') {
+            Some(value)
+        } else if rest.len() > 3 && rest[..3].eq_ignore_ascii_case("equ") && rest[3..].starts_with(char::is_whitespace) {
+            Some(&rest[3..])
+        } else {
+            None
+        };
+        if let Some(value) = definition {
+            if !is_identifier(word) {
+                return Err(error(format!("invalid constant name {}", word)));
+            }
+            push(Kind::Constant(word.to_string(), parse_expr(value.trim()).map_err(error)?));
+            continue;
+        }
+
+        match word.to_ascii_lowercase().as_str() {
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(error("includes are nested too deeply".to_string()));
+                }
+                let name = parse_string(rest).map_err(error)?;
+                let name = String::from_utf8_lossy(&name).into_owned();
+                let (path, included) = loader(file, &name).map_err(|io_error| error(format!("could not include {}: {}", name, io_error)))?;
+                parse_source(&path, &included, loader, depth + 1, statements)?;
+            }
+            "org" => push(Kind::Org(parse_expr(rest).map_err(error)?)),
+            directive @ ("db" | "dw") => {
+                let mut items = Vec::new();
+                for operand in split_operands(rest) {
+                    if operand.starts_with('"') {
+                        if directive == "dw" {
+                            return Err(error("strings are only allowed in db".to_string()));
+                        }
+                        items.push(DataItem::Text(parse_string(&operand).map_err(error)?));
+                    } else {
+                        items.push(DataItem::Value(parse_expr(&operand).map_err(error)?));
+                    }
+                }
+                if items.is_empty() {
+                    return Err(error(format!("{} needs at least one value", directive)));
+                }
+                push(Kind::Data(if directive == "db" { 1 } else { 2 }, items));
+            }
+            _ => push(Kind::Instruction(word.to_ascii_uppercase(), split_operands(rest))),
+        }
+    }
+    Ok(())
+}
+
+// Size in bytes of a statement.
+fn size(kind: &Kind) -> u32 {
+    match kind {
+        Kind::Data(width, items) => items.iter().map(|item| match item {
+            DataItem::Value(_) => *width,
+            DataItem::Text(text) => text.len() as u32,
+        }).sum(),
+        Kind::Instruction(_, operands) if is_long_operand(operands.get(1)) => 4,
+        Kind::Instruction(..) => 2,
+        _ => 0,
+    }
+}
+
+fn is_long_operand(operand: Option<&String>) -> bool {
+    operand.is_some_and(|operand| operand.len() > 4 && operand[..4].eq_ignore_ascii_case("long") && operand[4..].starts_with(char::is_whitespace))
+}
+
+struct Symbols {
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, Expr>,
+}
+
+impl Symbols {
+    fn eval(&self, expr: &Expr, here: u32, depth: usize) -> Result<i64, String> {
+        if depth > MAX_EXPRESSION_DEPTH {
+            return Err("constant defined in terms of itself".to_string());
+        }
+        let eval = |expr: &Expr| self.eval(expr, here, depth + 1);
+        Ok(match expr {
+            Expr::Number(value) => *value,
+            Expr::Here => here as i64,
+            Expr::Symbol(name) => match (self.labels.get(name), self.constants.get(name)) {
+                (Some(addr), _) => *addr as i64,
+                (None, Some(value)) => eval(value)?,
+                (None, None) => return Err(format!("undefined symbol {}", name)),
+            },
+            Expr::Negate(value) => eval(value)?.wrapping_neg(),
+            Expr::Not(value) => !eval(value)?,
+            Expr::Binary(operator, left, right) => {
+                let (left, right) = (eval(left)?, eval(right)?);
+                match operator {
+                    '+' => left.wrapping_add(right),
+                    '-' => left.wrapping_sub(right),
+                    '*' => left.wrapping_mul(right),
+                    '/' | '%' if right == 0 => return Err("division by zero".to_string()),
+                    '/' => left / right,
+                    '%' => left % right,
+                    '<' => left.checked_shl(right as u32).unwrap_or(0),
+                    '>' => left.checked_shr(right as u32).unwrap_or(0),
+                    '&' => left & right,
+                    '|' => left | right,
+                    _ => left ^ right,
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    // Vx-Vy, for the XO-CHIP register range loads.
+    VRange(u8, u8),
+    I,
+    // [I]
+    Memory,
+    DT,
+    ST,
+    K,
+    F,
+    HF,
+    B,
+    R,
+    Pitch,
+    Audio,
+    Long(i64),
+    Value(i64),
+}
+
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('V').or_else(|| text.strip_prefix('v'))?;
+    match u8::from_str_radix(digit, 16) {
+        Ok(reg) if digit.len() == 1 => Some(reg),
+        _ => None,
+    }
+}
+
+fn parse_operand(text: &str, symbols: &Symbols, here: u32) -> Result<Operand, String> {
+    if let Some(reg) = register(text) {
+        return Ok(Operand::V(reg));
+    }
+    if let Some((x, y)) = text.split_once('-') {
+        if let (Some(x), Some(y)) = (register(x.trim()), register(y.trim())) {
+            return Ok(Operand::VRange(x, y));
+        }
+    }
+    let keyword = match text.to_ascii_uppercase().as_str() {
+        "I" => Some(Operand::I),
+        "[I]" => Some(Operand::Memory),
+        "DT" => Some(Operand::DT),
+        "ST" => Some(Operand::ST),
+        "K" => Some(Operand::K),
+        "F" => Some(Operand::F),
+        "HF" => Some(Operand::HF),
+        "B" => Some(Operand::B),
+        "R" => Some(Operand::R),
+        "PITCH" => Some(Operand::Pitch),
+        "AUDIO" => Some(Operand::Audio),
+        _ => None,
+    };
+    if let Some(keyword) = keyword {
+        return Ok(keyword);
+    }
+    if is_long_operand(Some(&text.to_string())) {
+        return Ok(Operand::Long(symbols.eval(&parse_expr(text[4..].trim())?, here, 0)?));
+    }
+    Ok(Operand::Value(symbols.eval(&parse_expr(text)?, here, 0)?))
+}
+
+fn in_range(value: i64, bits: u32, what: &str) -> Result<u16, String> {
+    let max = (1i64 << bits) - 1;
+    if value < 0 || value > max {
+        return Err(format!("{} {} out of range 0-{}", what, value, max));
+    }
+    Ok(value as u16)
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    in_range(value, 12, "address")
+}
+
+fn nibble(value: i64) -> Result<u16, String> {
+    in_range(value, 4, "value")
+}
+
+// Bytes may also be given as negative numbers.
+fn byte(value: i64) -> Result<u16, String> {
+    if (-128..0).contains(&value) {
+        return Ok(value as u8 as u16);
+    }
+    in_range(value, 8, "byte")
+}
+
+const MNEMONICS: [&str; 49] = [
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP",
+    "SKNP", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "PLANE", "BGCOL", "ADDN", "COL", "SKP2", "SKNP2", "OUT", "IN", "MEGAOFF",
+    "MEGAON", "LDPAL", "SPRW", "SPRH", "ALPHA", "DIGISND", "STOPSND", "BMODE", "CCOL", "DB", "DW", "ORG", "INCLUDE",
+];
+
+// Encode one instruction as its opcode words.
+fn encode(mnemonic: &str, operands: &[Operand], variant: Variant) -> Result<Vec<u16>, String> {
+    use Operand::*;
+    let xy = |x: &u8, y: &u8| ((*x as u16) << 8) | ((*y as u16) << 4);
+    let x = |x: &u8| (*x as u16) << 8;
+    let opcode = match (mnemonic, operands) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Value(a)]) => address(*a)?,
+        ("JP", [Value(a)]) => 0x1000 | address(*a)?,
+        ("JP", [V(0), Value(a)]) => 0xB000 | address(*a)?,
+        ("CALL", [Value(a)]) => 0x2000 | address(*a)?,
+        ("SE", [V(vx), Value(k)]) => 0x3000 | x(vx) | byte(*k)?,
+        ("SNE", [V(vx), Value(k)]) => 0x4000 | x(vx) | byte(*k)?,
+        ("SE", [V(vx), V(vy)]) => 0x5000 | xy(vx, vy),
+        ("SNE", [V(vx), V(vy)]) => 0x9000 | xy(vx, vy),
+        ("LD", [V(vx), Value(k)]) => 0x6000 | x(vx) | byte(*k)?,
+        ("ADD", [V(vx), Value(k)]) => 0x7000 | x(vx) | byte(*k)?,
+        ("LD", [V(vx), V(vy)]) => 0x8000 | xy(vx, vy),
+        ("OR", [V(vx), V(vy)]) => 0x8001 | xy(vx, vy),
+        ("AND", [V(vx), V(vy)]) => 0x8002 | xy(vx, vy),
+        ("XOR", [V(vx), V(vy)]) => 0x8003 | xy(vx, vy),
+        ("ADD", [V(vx), V(vy)]) => 0x8004 | xy(vx, vy),
+        ("SUB", [V(vx), V(vy)]) => 0x8005 | xy(vx, vy),
+        ("SHR", [V(vx)]) => 0x8006 | xy(vx, vx),
+        ("SHR", [V(vx), V(vy)]) => 0x8006 | xy(vx, vy),
+        ("SUBN", [V(vx), V(vy)]) => 0x8007 | xy(vx, vy),
+        ("SHL", [V(vx)]) => 0x800E | xy(vx, vx),
+        ("SHL", [V(vx), V(vy)]) => 0x800E | xy(vx, vy),
+        ("LD", [I, Value(a)]) => 0xA000 | address(*a)?,
+        // MEGA-CHIP addresses have 24 bits, the high byte goes in the opcode
+        ("LD", [I, Long(a)]) => {
+            return match variant {
+                Variant::XoChip => Ok(vec![0xF000, in_range(*a, 16, "address")?]),
+                Variant::MegaChip => {
+                    let a = in_range(*a >> 16, 8, "address").map(|_| *a as u32).map_err(|_| format!("address {} out of range 0-{}", a, (1 << 24) - 1))?;
+                    Ok(vec![0x0100 | (a >> 16) as u16, a as u16])
+                }
+                _ => Err("LD I, LONG needs the xochip or megachip variant".to_string()),
+            };
+        }
+        ("RND", [V(vx), Value(k)]) => 0xC000 | x(vx) | byte(*k)?,
+        ("DRW", [V(vx), V(vy), Value(n)]) => 0xD000 | xy(vx, vy) | nibble(*n)?,
+        ("SKP", [V(vx)]) => 0xE09E | x(vx),
+        ("SKNP", [V(vx)]) => 0xE0A1 | x(vx),
+        ("LD", [V(vx), DT]) => 0xF007 | x(vx),
+        ("LD", [V(vx), K]) => 0xF00A | x(vx),
+        ("LD", [DT, V(vx)]) => 0xF015 | x(vx),
+        ("LD", [ST, V(vx)]) => 0xF018 | x(vx),
+        ("ADD", [I, V(vx)]) => 0xF01E | x(vx),
+        ("LD", [F, V(vx)]) => 0xF029 | x(vx),
+        ("LD", [HF, V(vx)]) => 0xF030 | x(vx),
+        ("LD", [B, V(vx)]) => 0xF033 | x(vx),
+        ("LD", [Pitch, V(vx)]) => 0xF03A | x(vx),
+        ("LD", [Memory, V(vx)]) => 0xF055 | x(vx),
+        ("LD", [V(vx), Memory]) => 0xF065 | x(vx),
+        ("LD", [R, V(vx)]) => 0xF075 | x(vx),
+        ("LD", [V(vx), R]) => 0xF085 | x(vx),
+        ("LD", [Memory, VRange(vx, vy)]) => 0x5002 | xy(vx, vy),
+        ("LD", [VRange(vx, vy), Memory]) => 0x5003 | xy(vx, vy),
+        ("LD", [Audio, Memory]) => 0xF002,
+        ("SCD", [Value(n)]) => 0x00C0 | nibble(*n)?,
+        ("SCU", [Value(n)]) if variant == Variant::MegaChip => 0x00B0 | nibble(*n)?,
+        ("SCU", [Value(n)]) => 0x00D0 | nibble(*n)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("PLANE", [Value(n)]) => 0xF001 | (nibble(*n)? << 8),
+        ("BGCOL", []) => 0x02A0,
+        ("ADDN", [V(vx), V(vy)]) => 0x5001 | xy(vx, vy),
+        ("COL", [V(vx), V(vy), Value(n)]) => 0xB000 | xy(vx, vy) | nibble(*n)?,
+        ("SKP2", [V(vx)]) => 0xE0F2 | x(vx),
+        ("SKNP2", [V(vx)]) => 0xE0F5 | x(vx),
+        ("OUT", [V(vx)]) => 0xF0F8 | x(vx),
+        ("IN", [V(vx)]) => 0xF0FB | x(vx),
+        ("MEGAOFF", []) => 0x0010,
+        ("MEGAON", []) => 0x0011,
+        ("LDPAL", [Value(n)]) => 0x0200 | byte(*n)?,
+        ("SPRW", [Value(n)]) => 0x0300 | byte(*n)?,
+        ("SPRH", [Value(n)]) => 0x0400 | byte(*n)?,
+        ("ALPHA", [Value(n)]) => 0x0500 | byte(*n)?,
+        ("DIGISND", [Value(n)]) => 0x0600 | nibble(*n)?,
+        ("STOPSND", []) => 0x0700,
+        ("BMODE", [Value(n)]) => 0x0800 | nibble(*n)?,
+        ("CCOL", [Value(n)]) => 0x0900 | byte(*n)?,
+        _ if MNEMONICS.contains(&mnemonic) => return Err(format!("invalid operands for {}", mnemonic)),
+        _ => return Err(format!("unknown instruction {}", mnemonic)),
+    };
+    Ok(vec![opcode])
+}
+
+// Assemble source text. `name` identifies it in errors, `loader` reads
+// included files.
+pub fn assemble(name: &str, source: &str, variant: Variant, loader: &Loader) -> Result<Assembly, AsmError> {
+    let mut statements = Vec::new();
+    parse_source(name, source, loader, 0, &mut statements)?;
+
+    // First pass: where everything goes
+    let start = variant.program_start() as u32;
+    let mut symbols = Symbols { labels: HashMap::new(), constants: HashMap::new() };
+    let mut addresses = Vec::new();
+    let mut addr = start;
+    for statement in statements.iter() {
+        match &statement.kind {
+            Kind::Label(name) | Kind::Constant(name, _) if symbols.labels.contains_key(name) || symbols.constants.contains_key(name) => {
+                return Err(statement.error(format!("{} is already defined", name)));
+            }
+            Kind::Label(name) => {
+                symbols.labels.insert(name.clone(), addr);
+            }
+            Kind::Constant(name, value) => {
+                symbols.constants.insert(name.clone(), value.clone());
+            }
+            Kind::Org(target) => {
+                // Only what comes before can decide where the code goes
+                let target = symbols.eval(target, addr, 0).map_err(|message| statement.error(message))?;
+                if target < addr as i64 {
+                    return Err(statement.error(format!("org #{:X} is before the current address #{:X}", target, addr)));
+                }
+                addr = target as u32;
+            }
+            _ => {}
+        }
+        addresses.push(addr);
+        addr += size(&statement.kind);
+        if addr as usize > variant.memory_size() {
+            return Err(statement.error("program doesn't fit in memory".to_string()));
+        }
+    }
+
+    // Second pass: the bytes
+    let mut rom = Vec::new();
+    for (statement, &addr) in statements.iter().zip(addresses.iter()) {
+        let error = |message: String| statement.error(message);
+        rom.resize((addr - start) as usize, 0);
+        match &statement.kind {
+            Kind::Data(width, items) => {
+                for (index, item) in items.iter().enumerate() {
+                    match item {
+                        DataItem::Text(text) => rom.extend_from_slice(text),
+                        DataItem::Value(expr) => {
+                            let value = symbols.eval(expr, addr, 0).map_err(error)?;
+                            if *width == 1 {
+                                rom.push(byte(value).map_err(|message| error(format!("item {}: {}", index + 1, message)))? as u8);
+                            } else {
+                                let word = if (-0x8000..0).contains(&value) { value as u16 } else { in_range(value, 16, "word").map_err(error)? };
+                                rom.extend_from_slice(&word.to_be_bytes());
+                            }
+                        }
+                    }
+                }
+            }
+            Kind::Instruction(mnemonic, operands) => {
+                let operands = operands.iter().map(|operand| parse_operand(operand, &symbols, addr)).collect::<Result<Vec<_>, _>>().map_err(error)?;
+                for word in encode(mnemonic, &operands, variant).map_err(error)? {
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut labels: Vec<(String, u32)> = symbols.labels.into_iter().collect();
+    labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    Ok(Assembly { rom, symbols: labels })
+}
+
+// Assemble a file, includes are read relative to it.
+pub fn assemble_file(path: &str, variant: Variant) -> Result<Assembly, AsmError> {
+    let source = fs::read_to_string(path).map_err(|error| AsmError { file: path.to_string(), line: 0, message: error.to_string() })?;
+    assemble(path, &source, variant, &load_relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::Disassembly;
+    use crate::rom::Rom;
+
+    fn no_includes(_: &str, name: &str) -> io::Result<(String, String)> {
+        Err(io::Error::new(io::ErrorKind::NotFound, name.to_string()))
+    }
+
+    fn assemble_chip8(source: &str) -> Result<Assembly, AsmError> {
+        assemble("test.asm", source, Variant::Chip8, &no_includes)
+    }
+
+    #[test]
+    fn assembles_instructions_and_labels() {
+        let source = "
+            SPEED equ 2 * 3   ; forward references are fine
+            start:
+                LD I, sprite
+                LD V0, -1
+                DRW V0, V1, SPEED - 1
+                ADD V0, SPEED
+                SE V0, V1
+                JP start
+            sprite: db %11110000, #90, \"AB\"
+                dw $+1
+            LD V2, SPEED%4
+        ";
+        let assembly = assemble_chip8(source).unwrap();
+        assert_eq!(
+            assembly.rom,
+            [0xA2, 0x0C, 0x60, 0xFF, 0xD0, 0x15, 0x70, 0x06, 0x50, 0x10, 0x12, 0x00, 0xF0, 0x90, 0x41, 0x42, 0x02, 0x11, 0x62, 0x02]
+        );
+        assert_eq!(assembly.symbol_file(), "0200 start\n020C sprite\n");
+    }
+
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        let source = "
+            CLS
+            LD V3, K
+            LD [I], V3
+            LD V1-V2, [I]
+            SHR V4, V5
+            SKNP VA
+            LD HF, V2
+            PLANE 3
+            LD I, LONG #1234
+            SCU 4
+            EXIT
+        ";
+        let assembly = assemble("test.asm", source, Variant::XoChip, &no_includes).unwrap();
+        let listing = Disassembly::linear(&Rom::from_bytes(assembly.rom).unwrap(), Variant::XoChip).listing();
+        let mnemonics: Vec<&str> = listing.lines().map(|line| line[22..].trim()).collect();
+        let expected: Vec<&str> = source.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+        assert_eq!(mnemonics, expected);
+    }
+
+    #[test]
+    fn org_pads_with_zeros() {
+        let assembly = assemble_chip8("CLS\norg 0x206\nend: RET").unwrap();
+        assert_eq!(assembly.rom, [0x00, 0xE0, 0, 0, 0, 0, 0x00, 0xEE]);
+        assert_eq!(assembly.symbols, [("end".to_string(), 0x206)]);
+    }
+
+    #[test]
+    fn includes_other_files() {
+        let loader = |from: &str, name: &str| {
+            assert_eq!((from, name), ("main.asm", "sprites.asm"));
+            Ok(("sprites.asm".to_string(), "ball: db #60, #F0".to_string()))
+        };
+        let assembly = assemble("main.asm", "LD I, ball\ninclude \"sprites.asm\"", Variant::Chip8, &loader).unwrap();
+        assert_eq!(assembly.rom, [0xA2, 0x02, 0x60, 0xF0]);
+    }
+
+    #[test]
+    fn reports_errors_with_location() {
+        let error = |source: &str| assemble_chip8(source).unwrap_err();
+        assert_eq!(error("CLS\nJP nowhere").to_string(), "test.asm:2: undefined symbol nowhere");
+        assert_eq!(error("LD V0, 256").message, "byte 256 out of range 0-255");
+        assert_eq!(error("MOV V0, V1").message, "unknown instruction MOV");
+        assert_eq!(error("DRW V0, 5").message, "invalid operands for DRW");
+        assert_eq!(error("a: CLS\na: RET").message, "a is already defined");
+        assert_eq!(error("X = Y\nY = X\nLD V0, X").message, "constant defined in terms of itself");
+        assert_eq!(error("include \"missing.asm\"").line, 1);
+    }
+
+    #[test]
+    fn writes_rom_and_symbols_next_to_source() {
+        let assembly = assemble_chip8("start: JP start").unwrap();
+        let dir = std::env::temp_dir().join(format!("rchip8-asm-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("game.asm").to_string_lossy().into_owned();
+        let symbols = dir.join("game.sym").to_string_lossy().into_owned();
+        assembly.write(&source, None, Some(&symbols)).unwrap();
+        assert_eq!(fs::read(dir.join("game.ch8")).unwrap(), [0x12, 0x00]);
+        assert_eq!(fs::read_to_string(&symbols).unwrap(), "0200 start\n");
+        let error = assembly.write(&source, Some(&dir.join("missing/game.ch8").to_string_lossy()), None).unwrap_err();
+        assert!(error.starts_with("Problem writing"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use std::env;
+use std::process;
+
+use rchip8::disasm::Disassembly;
+use rchip8::load_rom;
+use rchip8::variant::{Variant, VARIANTS};
+
+const USAGE: &str = "usage: rchip8-disasm [options] ROM
+
+Print a listing of the ROM, with the code found by following execution from
+the program start and everything else shown as data.
+
+options:
+    --variant NAME   instruction set: chip8, chip8-hires, chip8x, schip,
+                     xochip or megachip (default chip8)
+    --linear         decode every byte pair as an instruction instead";
+
+struct Options {
+    rom: String,
+    variant: Variant,
+    linear: bool,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut rom = None;
+    let mut variant = Variant::default();
+    let mut linear = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--variant" => {
+                let value = args.next().ok_or("--variant needs a value")?;
+                variant = Variant::from_name(&value).ok_or(format!("unknown variant {}, expected one of {}", value, VARIANTS.join(", ")))?;
+            }
+            "--linear" => linear = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let rom = rom.ok_or("no ROM file given")?;
+    Ok(Options { rom, variant, linear })
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let rom = match load_rom(&options.rom) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Problem opening ROM file: {}", error);
+            process::exit(1);
+        }
+    };
+
+    let disassembly = if options.linear {
+        Disassembly::linear(&rom, options.variant)
+    } else {
+        Disassembly::new(&rom, options.variant)
+    };
+    print!("{}", disassembly.listing());
+}
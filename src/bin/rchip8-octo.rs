@@ -0,0 +1,66 @@
+use std::env;
+use std::process;
+
+use rchip8::octo::compile_file;
+
+const USAGE: &str = "usage: rchip8-octo [options] SOURCE
+
+Compile an Octo program into a ROM. Run it with the variant it was written
+for, usually --variant schip or --variant xochip.
+
+options:
+    -o FILE          ROM to write (default SOURCE with a .ch8 extension)
+    --symbols FILE   also write the address of every label to FILE";
+
+struct Options {
+    source: String,
+    output: Option<String>,
+    symbols: Option<String>,
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("-o needs a value")?),
+            "--symbols" => symbols = Some(args.next().ok_or("--symbols needs a value")?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if source.is_none() => source = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    let source = source.ok_or("no source file given")?;
+    Ok(Options { source, output, symbols })
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let assembly = match compile_file(&options.source) {
+        Ok(assembly) => assembly,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    if let Err(message) = assembly.write(&options.source, options.output.as_deref(), options.symbols.as_deref()) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
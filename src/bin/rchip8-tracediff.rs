@@ -0,0 +1,134 @@
+use std::env;
+use std::fs;
+use std::process;
+use std::str::FromStr;
+
+use rchip8::chip8::DEFAULT_IPS;
+use rchip8::quirks::{Quirks, PRESETS};
+use rchip8::random::{Random, RandomMode};
+use rchip8::tracediff::{compare, parse_reference, Comparison};
+use rchip8::variant::{Variant, VARIANTS};
+use rchip8::{load_rom, Chip8};
+
+const USAGE: &str = "usage: rchip8-tracediff [options] ROM TRACE
+
+Run the ROM without a window and compare the machine before every
+instruction with a reference trace in the --trace format, such as one
+converted from another emulator's log. Reports the first difference with
+the instructions around it.
+
+options:
+    --variant NAME   instruction set: chip8, chip8-hires, chip8x, schip,
+                     xochip or megachip (default chip8)
+    --quirks NAME    interpreter quirks preset: vip, chip48, schip, xochip
+                     or modern (default: the variant's preset)
+    --ips N          instructions executed per second, decides when the
+                     delay timer counts down (default 700)
+    --seed N         seed for the random number generator (default 0)
+    --context N      instructions to show around a difference (default 5)";
+
+struct Options {
+    rom: String,
+    trace: String,
+    variant: Variant,
+    quirks: Option<Quirks>,
+    ips: u32,
+    seed: u64,
+    context: usize,
+}
+
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("{} needs a value", flag))?;
+    value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut files = Vec::new();
+    let mut variant = Variant::default();
+    let mut quirks = None;
+    let mut ips = DEFAULT_IPS;
+    let mut seed = 0;
+    let mut context = 5;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--variant" => {
+                let value = args.next().ok_or("--variant needs a value")?;
+                variant = Variant::from_name(&value).ok_or(format!("unknown variant {}, expected one of {}", value, VARIANTS.join(", ")))?;
+            }
+            "--quirks" => {
+                let value = args.next().ok_or("--quirks needs a value")?;
+                quirks = Some(Quirks::preset(&value).ok_or(format!("unknown quirks preset {}, expected one of {}", value, PRESETS.join(", ")))?);
+            }
+            "--ips" => ips = parse_number(&arg, args.next())?,
+            "--seed" => seed = parse_number(&arg, args.next())?,
+            "--context" => context = parse_number(&arg, args.next())?,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if files.len() < 2 => files.push(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if files.len() < 2 {
+        return Err("a ROM and a trace file are needed".to_string());
+    }
+    if ips == 0 {
+        return Err("--ips must be at least 1".to_string());
+    }
+    let trace = files.pop().unwrap();
+    let rom = files.pop().unwrap();
+    Ok(Options { rom, trace, variant, quirks, ips, seed, context })
+}
+
+fn run(options: &Options) -> Result<bool, String> {
+    let rom = load_rom(&options.rom).map_err(|error| format!("Problem opening ROM file: {}", error))?;
+    let mut chip8 = Chip8::new(rom, options.variant).map_err(|error| format!("Problem loading ROM: {}", error))?;
+    chip8.ips = options.ips;
+    chip8.cpu.quirks = options.quirks.unwrap_or_else(|| options.variant.default_quirks());
+    chip8.cpu.rng = Random::new(options.seed, RandomMode::Splitmix);
+
+    let text = fs::read_to_string(&options.trace).map_err(|error| format!("Problem reading trace {}: {}", options.trace, error))?;
+    let reference = parse_reference(&text).map_err(|error| format!("{}: {}", options.trace, error))?;
+    match compare(&mut chip8, &reference, options.context) {
+        Comparison::Match => {
+            println!("all {} records of the trace match", reference.len());
+            Ok(true)
+        }
+        Comparison::Mismatch(mismatch) => {
+            print!("{}", mismatch);
+            Ok(false)
+        }
+        Comparison::Exited(line) => {
+            println!("the program exited before line {} of the trace", line);
+            Ok(false)
+        }
+        Comparison::Fault(line, fault) => {
+            println!("CPU fault before line {} of the trace: {}", line, fault);
+            Ok(false)
+        }
+    }
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&options) {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("{}", message);
+            process::exit(2);
+        }
+    }
+}
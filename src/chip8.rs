@@ -0,0 +1,213 @@
+use crate::cpu::{self, CPU};
+use crate::display::FrameBuffer;
+use crate::fault::{CpuFault, StepOutcome};
+use crate::input::InputMap;
+use crate::rom::{Rom, RomError};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::trace::Tracer;
+use crate::variant::Variant;
+
+// The delay and sound timers count down at 60 Hz, frames run at the same rate.
+pub const TIMER_HZ: u32 = 60;
+// Roughly the speed of the original COSMAC VIP interpreter running typical
+// games.
+pub const DEFAULT_IPS: u32 = 700;
+
+// A complete CHIP-8 machine: CPU state (memory, registers, stack and
+// timers), the display and the keypad. Frontends feed key state into
+// `input`, call `run_frame` at 60 Hz and read `frame_buffer` to render.
+pub struct Chip8 {
+    // The program the machine was started with.
+    pub rom: Rom,
+    pub cpu: CPU,
+    pub frame_buffer: FrameBuffer,
+    pub input: InputMap,
+    // Emulated clock speed in instructions per second, must not be zero.
+    pub ips: u32,
+    // Fraction of an instruction carried over between frames, in 1/TIMER_HZ
+    // units. Lets speeds that aren't a multiple of 60 run at the exact rate.
+    cycle_credit: u32,
+    // Set once the program executes the SUPER-CHIP exit instruction.
+    exited: bool,
+    // Writes every instruction executed to a trace when set. Not part of
+    // the machine state.
+    pub tracer: Option<Tracer>,
+}
+
+impl Chip8 {
+    // Fails if the ROM is too large for the variant's memory.
+    pub fn new(rom: Rom, variant: Variant) -> Result<Chip8, RomError> {
+        Ok(Chip8 {
+            cpu: cpu::init_cpu(&rom, variant)?,
+            rom,
+            frame_buffer: FrameBuffer::for_variant(variant),
+            input: InputMap::new(),
+            ips: DEFAULT_IPS,
+            cycle_credit: 0,
+            exited: false,
+            tracer: None,
+        })
+    }
+
+    // Execute a single instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuFault> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.record(&self.cpu);
+        }
+        let outcome = cpu::run(&mut self.cpu, &mut self.frame_buffer, &mut self.input)?;
+        if outcome == StepOutcome::Exited {
+            self.exited = true;
+        }
+        Ok(outcome)
+    }
+
+    // Whether the program has exited, running it further does nothing.
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    // Number of instructions the next call to `run_frame` will execute.
+    pub fn next_frame_cycles(&self) -> u32 {
+        (self.cycle_credit + self.ips) / TIMER_HZ
+    }
+
+    // Run one 60 Hz frame: execute a frame's worth of instructions and then
+    // update the timers once. Returns the number of instructions executed,
+    // which is less than `next_frame_cycles` if a DRW waited for the display
+    // or the program exited.
+    // A fault stops the frame early, without updating the timers.
+    pub fn run_frame(&mut self) -> Result<u32, CpuFault> {
+        self.run_frame_with(|_| true)
+    }
+
+    // Like `run_frame`, but calls `inspect` before every instruction. The
+    // frame ends right there, without updating the timers, when it returns
+    // false.
+    pub fn run_frame_with<F: FnMut(&Chip8) -> bool>(&mut self, mut inspect: F) -> Result<u32, CpuFault> {
+        let cycles = self.next_frame_cycles();
+        self.cycle_credit = (self.cycle_credit + self.ips) % TIMER_HZ;
+        let mut executed = 0;
+        while executed < cycles {
+            if !inspect(self) {
+                return Ok(executed);
+            }
+            executed += 1;
+            match self.step()? {
+                StepOutcome::WaitForFrame | StepOutcome::Exited => break,
+                _ => {}
+            }
+        }
+        self.tick_timers();
+        Ok(executed)
+    }
+
+    // Decrement the delay and sound timers, should be called at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        if self.cpu.DT > 0 {
+            self.cpu.DT -= 1;
+        }
+        if self.cpu.ST > 0 {
+            self.cpu.ST -= 1;
+        }
+    }
+
+    // The buzzer sounds for as long as the sound timer is non-zero.
+    pub fn sound_active(&self) -> bool {
+        self.cpu.ST > 0
+    }
+
+    // Write everything but the ROM and the speed to a save state.
+    pub(crate) fn save_machine(&self, out: &mut StateWriter) {
+        self.cpu.save(out);
+        self.frame_buffer.save(out);
+        self.input.save(out);
+        out.u32(self.cycle_credit);
+        out.bool(self.exited);
+    }
+
+    // Replace the machine state with one written by `save_machine`, which
+    // must be the rest of the input. Nothing changes if the state can't be
+    // read.
+    pub(crate) fn load_machine(&mut self, input: &mut StateReader) -> Result<(), StateError> {
+        let cpu = CPU::load(input)?;
+        let frame_buffer = FrameBuffer::load(input)?;
+        let keys = InputMap::load(input)?;
+        let cycle_credit = input.u32()? % TIMER_HZ;
+        let exited = input.bool()?;
+        if !input.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        self.cpu = cpu;
+        self.frame_buffer = frame_buffer;
+        self.input = keys;
+        self.cycle_credit = cycle_credit;
+        self.exited = exited;
+        Ok(())
+    }
+
+    // Samples per second to play the XO-CHIP audio pattern at. Pitch 64 is
+    // 4000 Hz and every 48 steps doubles or halves the rate.
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.cpu.pitch as f32 - 64.0) / 48.0)
+    }
+}
+
+#[cfg(test)]
+impl Chip8 {
+    // A machine running `program`, for tests.
+    pub(crate) fn from_program(program: &[u8], variant: Variant) -> Chip8 {
+        Chip8::new(Rom::from_bytes(program.to_vec()).unwrap(), variant).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 200: LD V0, 3; 202: LD ST, V0; 204: JP 204
+    const PROGRAM: [u8; 6] = [0x60, 0x03, 0xF0, 0x18, 0x12, 0x04];
+
+    #[test]
+    fn sound_timer_counts_down_to_silence() {
+        let mut chip8 = Chip8::from_program(&PROGRAM, Variant::Chip8);
+        assert!(!chip8.sound_active());
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.cpu.ST, 3);
+        assert!(chip8.sound_active());
+        for expected in [2, 1, 0] {
+            chip8.tick_timers();
+            assert_eq!(chip8.cpu.ST, expected);
+        }
+        assert!(!chip8.sound_active());
+        chip8.tick_timers();
+        assert_eq!(chip8.cpu.ST, 0);
+    }
+
+    #[test]
+    fn spreads_odd_speeds_over_frames() {
+        // 200: JP 200
+        let mut chip8 = Chip8::from_program(&[0x12, 0x00], Variant::Chip8);
+        let mut total = 0;
+        for _ in 0..TIMER_HZ {
+            let executed = chip8.run_frame().unwrap();
+            assert!(executed == 11 || executed == 12, "{} instructions in a frame", executed);
+            total += executed;
+        }
+        assert_eq!(total, 700);
+    }
+
+    #[test]
+    fn timers_tick_once_per_frame_at_any_speed() {
+        for ips in [1, 59, 60, 700, 1000, 100_000] {
+            let mut chip8 = Chip8::from_program(&[0x12, 0x00], Variant::Chip8);
+            chip8.ips = ips;
+            chip8.cpu.DT = 200;
+            chip8.cpu.ST = 100;
+            for _ in 0..30 {
+                chip8.run_frame().unwrap();
+            }
+            assert_eq!((chip8.cpu.DT, chip8.cpu.ST), (170, 70), "at {} IPS", ips);
+        }
+    }
+}
@@ -0,0 +1,1310 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use log::trace;
+
+
+use crate::display::{FrameBuffer, SpriteWrap, HEIGHT, HIRES_HEIGHT, HIRES_WIDTH, WIDTH, ZONE_WIDTH};
+use crate::fault::{CpuFault, FaultKind, StepOutcome};
+use crate::input::InputMap;
+use crate::instruction::{decode, is_long, Instruction};
+use crate::megachip::{BlendMode, MegaChip, Sample, FONT_COLOR};
+use crate::quirks::{IndexIncrement, Quirks};
+use crate::random::Random;
+use crate::rom::{Rom, RomError};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::variant::Variant;
+
+// Programs are loaded at 0x200, the region below it is reserved for the
+// interpreter (we only use it for the hex font).
+pub const PROGRAM_START: usize = 512;
+pub const MEMORY_SIZE: usize = 4096;
+// XO-CHIP extends the address space to 64 KiB.
+pub const XO_MEMORY_SIZE: usize = 65536;
+// MEGACHIP has a 24 bit address space.
+pub const MEGA_MEMORY_SIZE: usize = 1 << 24;
+// The largest ROM any variant can load.
+pub const MAX_ROM_SIZE: usize = MEGA_MEMORY_SIZE - PROGRAM_START;
+// Pitch that plays XO-CHIP audio patterns at 4000 samples per second.
+pub const DEFAULT_PITCH: u8 = 64;
+
+const HEX_SPRITES: [u8; 80] = [0xF0, 0x90, 0x90, 0x90, 0xF0,
+                               0x20, 0x60, 0x20, 0x20, 0x70,
+                               0xF0, 0x10, 0xF0, 0x80, 0xF0,
+                               0xF0, 0x10, 0xF0, 0x10, 0xF0,
+                               0x90, 0x90, 0xF0, 0x10, 0x10,
+                               0xF0, 0x80, 0xF0, 0x10, 0xF0,
+                               0xF0, 0x90, 0xF0, 0x90, 0xF0,
+                               0xF0, 0x10, 0x20, 0x40, 0x40,
+                               0xF0, 0x90, 0xF0, 0x90, 0xF0,
+                               0xF0, 0x90, 0xF0, 0x10, 0xF0,
+                               0xF0, 0x90, 0xF0, 0x90, 0x90,
+                               0xE0, 0x90, 0xE0, 0x90, 0xE0,
+                               0xF0, 0x80, 0x80, 0x80, 0xF0,
+                               0xE0, 0x90, 0x90, 0x90, 0xE0,
+                               0xF0, 0x80, 0xF0, 0x80, 0xF0,
+                               0xF0, 0x80, 0xF0, 0x80, 0x80];
+
+// `JP #260` at the start of CHIP-8 hires programs, and where the CHIP-8 code
+// after the hires patch starts.
+const HIRES_ENTRY: [u8; 2] = [0x12, 0x60];
+const HIRES_START: usize = 0x2C0;
+
+// SUPER-CHIP 8x10 hex font, stored right after the small one.
+const BIG_HEX_SPRITES_START: usize = 80;
+const BIG_HEX_SPRITES: [u8; 160] = [0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+                                    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+                                    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+                                    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+                                    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+                                    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+                                    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+                                    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+                                    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+                                    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+                                    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0];
+
+#[allow(non_snake_case, clippy::upper_case_acronyms)]
+pub struct CPU {
+    // Stores the CPU State.
+
+    // RAM, the size depends on the variant
+    pub memory: Vec<u8>,
+    //registers
+    pub V: [u8; 16],
+    // 16 bits wide, except on MEGACHIP where it is 24
+    pub I: u32,
+    pub PC: u16,
+    pub SP: u8,
+    //stack
+    pub S: [u16; 16],
+    // Source of Cxkk random numbers, part of the state so that saved
+    // games continue the same way.
+    pub rng: Random,
+    //timers
+    pub DT: u8,
+    pub ST: u8,
+    // Interpreter variant behaviour.
+    pub quirks: Quirks,
+    // Which instruction set extensions are available.
+    pub variant: Variant,
+    // SUPER-CHIP RPL user flags, on the HP-48 these survived between runs.
+    pub rpl: [u8; 16],
+    // XO-CHIP audio pattern, 128 one bit samples played while the sound
+    // timer is active. None until the program loads one, which means the
+    // plain buzzer.
+    pub audio_pattern: Option<[u8; 16]>,
+    // XO-CHIP audio pattern playback pitch.
+    pub pitch: u8,
+    // MEGACHIP palette, sprite and sound settings.
+    pub megachip: MegaChip,
+    // Last value a CHIP-8X program wrote to the output port.
+    pub port: u8,
+}
+
+// Create a machine for the variant with the ROM loaded at its program start
+// address, 0x200 for most. Fails if the ROM doesn't fit in the variant's
+// memory.
+pub fn init_cpu(rom: &Rom, variant: Variant) -> Result<CPU, RomError> {
+    let start = variant.program_start();
+    let max = variant.memory_size() - start;
+    if rom.len() > max {
+        return Err(RomError::TooLarge { size: rom.len(), max });
+    }
+    // initialize CPU with correct values.
+    let mut memory = vec![0; variant.memory_size()];
+    memory[0..80].clone_from_slice(&HEX_SPRITES);
+    memory[BIG_HEX_SPRITES_START..BIG_HEX_SPRITES_START + 160].clone_from_slice(&BIG_HEX_SPRITES);
+    memory[start..start + rom.len()].clone_from_slice(rom.bytes());
+    // Hires programs start with a jump to the machine code patch that turns
+    // on the 64x64 mode, which then continues at 0x2C0.
+    let pc = if variant == Variant::Chip8Hires && rom.bytes().starts_with(&HIRES_ENTRY) { HIRES_START } else { start };
+    Ok(CPU {
+        memory,
+        V: [0; 16],
+        I: 0,
+        PC: pc as u16,
+        SP: 0,
+        S: [0; 16],
+        rng: Random::from_entropy(),
+        DT: 0,
+        ST: 0,
+        quirks: Quirks::default(),
+        variant,
+        rpl: [0; 16],
+        audio_pattern: None,
+        pitch: DEFAULT_PITCH,
+        megachip: MegaChip::new(),
+        port: 0,
+    })
+}
+
+impl Snapshot for CPU {
+    fn save(&self, out: &mut StateWriter) {
+        self.variant.save(out);
+        self.quirks.save(out);
+        out.bytes(&self.memory);
+        out.raw(&self.V);
+        out.u32(self.I);
+        out.u16(self.PC);
+        out.u8(self.SP);
+        for &addr in self.S.iter() {
+            out.u16(addr);
+        }
+        self.rng.save(out);
+        out.u8(self.DT);
+        out.u8(self.ST);
+        out.raw(&self.rpl);
+        match &self.audio_pattern {
+            Some(pattern) => {
+                out.bool(true);
+                out.raw(pattern);
+            }
+            None => out.bool(false),
+        }
+        out.u8(self.pitch);
+        self.megachip.save(out);
+        out.u8(self.port);
+    }
+
+    #[allow(non_snake_case)]
+    fn load(input: &mut StateReader) -> Result<CPU, StateError> {
+        let variant = Variant::load(input)?;
+        let quirks = Quirks::load(input)?;
+        let memory = input.bytes()?;
+        if memory.len() != variant.memory_size() {
+            return Err(StateError::Invalid("memory size"));
+        }
+        let mut V = [0; 16];
+        input.raw(&mut V)?;
+        let I = input.u32()? & variant.address_mask();
+        let PC = input.u16()?;
+        let SP = input.u8()?;
+        if SP as usize > 16 {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        let mut S = [0; 16];
+        for addr in S.iter_mut() {
+            *addr = input.u16()?;
+        }
+        let rng = Random::load(input)?;
+        let DT = input.u8()?;
+        let ST = input.u8()?;
+        let mut rpl = [0; 16];
+        input.raw(&mut rpl)?;
+        let audio_pattern = if input.bool()? {
+            let mut pattern = [0; 16];
+            input.raw(&mut pattern)?;
+            Some(pattern)
+        } else {
+            None
+        };
+        Ok(CPU {
+            memory,
+            V,
+            I,
+            PC,
+            SP,
+            S,
+            rng,
+            DT,
+            ST,
+            quirks,
+            variant,
+            rpl,
+            audio_pattern,
+            pitch: input.u8()?,
+            megachip: MegaChip::load(input)?,
+            port: input.u8()?,
+        })
+    }
+}
+
+// Read the two byte word at addr.
+fn read_word(cpu: &CPU, addr: u16) -> Option<u16> {
+    let addr = addr as usize;
+    if addr + 1 >= cpu.memory.len() {
+        return None;
+    }
+    Some(((cpu.memory[addr] as u16) << 8) | (cpu.memory[addr + 1] as u16))
+}
+
+// Fetch the two byte opcode at PC.
+pub fn fetch(cpu: &CPU) -> Option<u16> {
+    read_word(cpu, cpu.PC)
+}
+
+// Decode the instruction at addr without executing it, along with its opcode
+// and its length in bytes. None if it lies outside of memory.
+pub fn decode_at(cpu: &CPU, addr: u16) -> Option<(u16, Instruction, u16)> {
+    let opcode = read_word(cpu, addr)?;
+    if is_long(cpu.variant, opcode) {
+        let operand = read_word(cpu, addr.wrapping_add(2))?;
+        Some((opcode, decode(cpu.variant, opcode, operand), 4))
+    } else {
+        Some((opcode, decode(cpu.variant, opcode, 0), 2))
+    }
+}
+
+// The memory range [start, start+len) if it lies entirely within RAM.
+fn memory_range(cpu: &CPU, start: u32, len: usize) -> Result<Range<usize>, FaultKind> {
+    let start = start as usize;
+    let size = cpu.memory.len();
+    if start + len > size {
+        return Err(FaultKind::MemoryOutOfBounds { addr: size.max(start) });
+    }
+    Ok(start..start + len)
+}
+
+// Skip the instruction after the current one, which may be four bytes long.
+fn skip_next(cpu: &mut CPU) {
+    let next = cpu.PC.wrapping_add(2);
+    let long = read_word(cpu, next).is_some_and(|opcode| is_long(cpu.variant, opcode));
+    cpu.PC = next.wrapping_add(if long { 2 } else { 0 });
+}
+
+// The registers Vx through Vy, in the order 5xy2/5xy3 transfer them.
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    if x <= y {
+        (x as usize..=y as usize).collect()
+    } else {
+        (y as usize..=x as usize).rev().collect()
+    }
+}
+
+// The register 8xy6/8xyE read the value to shift from.
+fn shift_source(cpu: &CPU, x: u8, y: u8) -> usize {
+    if cpu.quirks.shift_uses_vy { y as usize } else { x as usize }
+}
+
+// Update I after Fx55/Fx65 transferred registers V0 through Vx.
+fn increment_index(cpu: &mut CPU, x: u8) {
+    cpu.I = match cpu.quirks.index_increment {
+        IndexIncrement::Unchanged => cpu.I,
+        IndexIncrement::ByX => cpu.I.wrapping_add(x as u32),
+        IndexIncrement::ByXPlusOne => cpu.I.wrapping_add(x as u32 + 1),
+    } & cpu.variant.address_mask();
+}
+
+pub fn run(cpu: &mut CPU, frame_buffer: &mut FrameBuffer, input: &mut InputMap) -> Result<StepOutcome, CpuFault> {
+    // run one instruction
+    let pc = cpu.PC;
+    let opcode = match fetch(cpu) {
+        Some(opcode) => opcode,
+        None => return Err(CpuFault { kind: FaultKind::PcOutOfBounds, pc, opcode: 0 }),
+    };
+    let operand = if is_long(cpu.variant, opcode) {
+        match read_word(cpu, pc.wrapping_add(2)) {
+            Some(operand) => operand,
+            None => return Err(CpuFault { kind: FaultKind::PcOutOfBounds, pc, opcode }),
+        }
+    } else {
+        0
+    };
+    let instruction = decode(cpu.variant, opcode, operand);
+
+    // --trace writes a structured version of this
+    trace!("{}: {:04X} {}", pc, opcode, instruction);
+
+    execute(cpu, frame_buffer, input, instruction).map_err(|kind| CpuFault { kind, pc, opcode })
+}
+
+// Execute an already decoded instruction. On a fault the machine state is
+// left untouched.
+pub fn execute(cpu: &mut CPU, frame_buffer: &mut FrameBuffer, input: &mut InputMap, instruction: Instruction) -> Result<StepOutcome, FaultKind> {
+    let mut pc_inc: bool = true;
+    let mut outcome = StepOutcome::Executed;
+
+    match instruction {
+        Instruction::Cls => {
+            // In MEGACHIP mode this also finishes the frame
+            if frame_buffer.is_mega() {
+                frame_buffer.present();
+                outcome = StepOutcome::WaitForFrame;
+            }
+            frame_buffer.clear();
+        }
+        Instruction::CycleBackground => {
+            frame_buffer.cycle_background();
+        }
+        Instruction::MegaOff => {
+            cpu.megachip.enabled = false;
+            frame_buffer.set_mega(false);
+        }
+        Instruction::MegaOn => {
+            cpu.megachip.enabled = true;
+            frame_buffer.set_mega(true);
+        }
+        Instruction::LdPalette(n) => {
+            let range = memory_range(cpu, cpu.I, n as usize * 4)?;
+            for (entry, color) in cpu.memory[range].chunks(4).enumerate() {
+                cpu.megachip.palette[entry + 1] = u32::from_be_bytes([color[0], color[1], color[2], color[3]]);
+            }
+        }
+        Instruction::SpriteWidth(n) => {
+            cpu.megachip.sprite_width = if n == 0 { 256 } else { n as usize };
+        }
+        Instruction::SpriteHeight(n) => {
+            cpu.megachip.sprite_height = if n == 0 { 256 } else { n as usize };
+        }
+        Instruction::ScreenAlpha(n) => {
+            cpu.megachip.screen_alpha = n;
+        }
+        Instruction::PlaySample(n) => {
+            // 6 byte header: rate, 24 bit length and a reserved byte
+            let header = memory_range(cpu, cpu.I, 6)?;
+            let header = &cpu.memory[header];
+            let rate = u16::from_be_bytes([header[0], header[1]]);
+            let len = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+            let data = memory_range(cpu, cpu.I + 6, len)?;
+            cpu.megachip.sample = Some(Arc::new(Sample {
+                rate,
+                data: cpu.memory[data].to_vec(),
+                looping: n == 0,
+            }));
+        }
+        Instruction::StopSample => {
+            cpu.megachip.sample = None;
+        }
+        Instruction::Blend(n) => {
+            cpu.megachip.blend = BlendMode::from_code(n);
+        }
+        Instruction::CollisionColor(n) => {
+            cpu.megachip.collision_color = n;
+        }
+        Instruction::Ret => {
+            if cpu.SP == 0 {
+                return Err(FaultKind::StackUnderflow);
+            }
+            cpu.SP -= 1;
+            cpu.PC = cpu.S[cpu.SP as usize];
+        }
+        Instruction::ScrollDown(n) => {
+            frame_buffer.scroll_down(n as usize);
+        }
+        Instruction::ScrollUp(n) => {
+            frame_buffer.scroll_up(n as usize);
+        }
+        Instruction::ScrollRight => {
+            frame_buffer.scroll_right(4);
+        }
+        Instruction::ScrollLeft => {
+            frame_buffer.scroll_left(4);
+        }
+        Instruction::Exit => {
+            // PC stays on 00FD, so the machine keeps reporting the exit
+            pc_inc = false;
+            outcome = StepOutcome::Exited;
+        }
+        Instruction::Low => {
+            frame_buffer.resize(WIDTH, HEIGHT);
+        }
+        Instruction::High => {
+            frame_buffer.resize(HIRES_WIDTH, HIRES_HEIGHT);
+        }
+        Instruction::Jp(addr) => {
+            cpu.PC = addr;
+            pc_inc = false;
+        }
+        Instruction::Call(addr) => {
+            if cpu.SP as usize >= cpu.S.len() {
+                return Err(FaultKind::StackOverflow);
+            }
+            cpu.S[cpu.SP as usize] = cpu.PC;
+            cpu.SP += 1;
+            cpu.PC = addr;
+            pc_inc = false;
+        }
+        Instruction::Se(x, kk) => {
+            if cpu.V[x as usize] == kk {
+                skip_next(cpu);
+            }
+        }
+        Instruction::Sne(x, kk) => {
+            if cpu.V[x as usize] != kk {
+                skip_next(cpu);
+            }
+        }
+        Instruction::SeReg(x, y) => {
+            if cpu.V[x as usize] == cpu.V[y as usize] {
+                skip_next(cpu);
+            }
+        }
+        Instruction::AddNibbles(x, y) => {
+            let vx = cpu.V[x as usize];
+            let vy = cpu.V[y as usize];
+            cpu.V[x as usize] = ((vx & 0x70) + (vy & 0x70)) & 0x70 | ((vx & 0x07) + (vy & 0x07)) & 0x07;
+        }
+        Instruction::LdStoreRange(x, y) => {
+            let registers = register_range(x, y);
+            let range = memory_range(cpu, cpu.I, registers.len())?;
+            for (offset, reg) in registers.into_iter().enumerate() {
+                cpu.memory[range.start + offset] = cpu.V[reg];
+            }
+        }
+        Instruction::LdLoadRange(x, y) => {
+            let registers = register_range(x, y);
+            let range = memory_range(cpu, cpu.I, registers.len())?;
+            for (offset, reg) in registers.into_iter().enumerate() {
+                cpu.V[reg] = cpu.memory[range.start + offset];
+            }
+        }
+        Instruction::Ld(x, kk) => {
+            cpu.V[x as usize] = kk;
+        }
+        Instruction::Add(x, kk) => {
+            cpu.V[x as usize] = cpu.V[x as usize].wrapping_add(kk);
+        }
+        Instruction::LdReg(x, y) => {
+            cpu.V[x as usize] = cpu.V[y as usize];
+        }
+        Instruction::Or(x, y) => {
+            cpu.V[x as usize] |= cpu.V[y as usize];
+            if cpu.quirks.logic_resets_vf {
+                cpu.V[0xF] = 0;
+            }
+        }
+        Instruction::And(x, y) => {
+            cpu.V[x as usize] &= cpu.V[y as usize];
+            if cpu.quirks.logic_resets_vf {
+                cpu.V[0xF] = 0;
+            }
+        }
+        Instruction::Xor(x, y) => {
+            cpu.V[x as usize] ^= cpu.V[y as usize];
+            if cpu.quirks.logic_resets_vf {
+                cpu.V[0xF] = 0;
+            }
+        }
+        // For the flag setting instructions VF is written after Vx, so when
+        // VF is the destination it ends up holding the flag.
+        Instruction::AddReg(x, y) => {
+            let sum = (cpu.V[x as usize] as u16) + (cpu.V[y as usize] as u16);
+            cpu.V[x as usize] = sum as u8;
+            cpu.V[0xF] = if sum > 255 { 1 } else { 0 };
+        }
+        Instruction::Sub(x, y) => {
+            // VF is set when there is no borrow
+            let v1 = cpu.V[x as usize];
+            let v2 = cpu.V[y as usize];
+
+            cpu.V[x as usize] = v1.wrapping_sub(v2);
+            cpu.V[0xF] = if v1 >= v2 { 1 } else { 0 };
+        }
+        Instruction::Shr(x, y) => {
+            // VF is the least significant bit of the value, which is halved
+            let value = cpu.V[shift_source(cpu, x, y)];
+            cpu.V[x as usize] = value >> 1;
+            cpu.V[0xF] = value & 0x01;
+        }
+        Instruction::Subn(x, y) => {
+            let v1 = cpu.V[x as usize];
+            let v2 = cpu.V[y as usize];
+
+            cpu.V[x as usize] = v2.wrapping_sub(v1);
+            cpu.V[0xF] = if v2 >= v1 { 1 } else { 0 };
+        }
+        Instruction::Shl(x, y) => {
+            // VF is set to the most significant bit shifted out
+            let value = cpu.V[shift_source(cpu, x, y)];
+            cpu.V[x as usize] = value << 1;
+            cpu.V[0xF] = (value & 0x80) >> 7;
+        }
+        Instruction::SneReg(x, y) => {
+            if cpu.V[x as usize] != cpu.V[y as usize] {
+                skip_next(cpu);
+            }
+        }
+        Instruction::LdI(addr) => {
+            cpu.I = addr as u32;
+        }
+        Instruction::JpV0(addr) => {
+            // With the quirk the top nibble of the address picks the register
+            let reg = if cpu.quirks.jump_uses_vx { (addr >> 8) as usize } else { 0 };
+            cpu.PC = addr.wrapping_add(cpu.V[reg] as u16);
+            pc_inc = false;
+        }
+        Instruction::ColorZone(x, y, n) => {
+            let color = cpu.V[y as usize] & 0x07;
+            let vx = cpu.V[x as usize] as usize;
+            let vx1 = cpu.V[(x as usize + 1) % 16] as usize;
+            if n == 0 {
+                // Whole 8x4 zones: the low nibble of Vx is the first zone
+                // column and the high nibble the number of extra columns,
+                // V(x+1) does the same for rows
+                frame_buffer.set_zone_color((vx & 0xF) * ZONE_WIDTH, (vx1 & 0xF) * 4, ((vx >> 4) + 1) * ZONE_WIDTH, ((vx1 >> 4) + 1) * 4, color);
+            } else {
+                // The zone containing pixel (Vx, V(x+1)), n rows high
+                frame_buffer.set_zone_color(vx, vx1, 1, n as usize, color);
+            }
+        }
+        Instruction::Rnd(x, kk) => {
+            cpu.V[x as usize] = cpu.rng.next_byte() & kk;
+        }
+        Instruction::Drw(vx, vy, n) if frame_buffer.is_mega() => {
+            let collision = if cpu.I < PROGRAM_START as u32 {
+                // The built in fonts are still bitmaps, drawn in a fixed
+                // palette entry
+                let glyph = memory_range(cpu, cpu.I, n as usize)?;
+                let mut sprite = Vec::with_capacity(n as usize * 8);
+                for byte in &cpu.memory[glyph] {
+                    for bit in 0..8 {
+                        sprite.push(if byte & (0x80 >> bit) != 0 { FONT_COLOR } else { 0 });
+                    }
+                }
+                frame_buffer.draw_mega_sprite(cpu.V[vx as usize], cpu.V[vy as usize], 8, &sprite, &cpu.megachip)
+            } else {
+                let width = cpu.megachip.sprite_width.max(1);
+                let sprite = memory_range(cpu, cpu.I, width * cpu.megachip.sprite_height)?;
+                frame_buffer.draw_mega_sprite(cpu.V[vx as usize], cpu.V[vy as usize], width, &cpu.memory[sprite], &cpu.megachip)
+            };
+            cpu.V[0xF] = if collision { 1 } else { 0 };
+        }
+        Instruction::Drw(vx, vy, n) => {
+            // SUPER-CHIP Dxy0 draws 16 rows of 2 bytes
+            let (len, row_bytes) = if n == 0 && cpu.variant.has_schip() { (32, 2) } else { (n as usize, 1) };
+            // With several XO-CHIP planes selected each gets its own sprite
+            let planes = frame_buffer.planes().count_ones().max(1) as usize;
+            let sprite = memory_range(cpu, cpu.I, len * planes)?;
+            let wrap = if cpu.quirks.clip_sprites { SpriteWrap::Clip } else { SpriteWrap::Wrap };
+            let collision = frame_buffer.draw_sprite(cpu.V[vx as usize], cpu.V[vy as usize], &cpu.memory[sprite], row_bytes, wrap);
+            cpu.V[0xF] = if collision { 1 } else { 0 };
+            if cpu.quirks.display_wait {
+                outcome = StepOutcome::WaitForFrame;
+            }
+        }
+        Instruction::LdILong(addr) => {
+            cpu.I = addr;
+            // Step over the address word as well
+            cpu.PC = cpu.PC.wrapping_add(2);
+        }
+        Instruction::Plane(n) => {
+            frame_buffer.select_planes(n);
+        }
+        Instruction::LdAudio => {
+            let range = memory_range(cpu, cpu.I, 16)?;
+            let mut pattern = [0; 16];
+            pattern.copy_from_slice(&cpu.memory[range]);
+            cpu.audio_pattern = Some(pattern);
+        }
+        Instruction::Skp(x) => {
+            if input.is_pressed(cpu.V[x as usize]) {
+                skip_next(cpu);
+            }
+        }
+        Instruction::Sknp(x) => {
+            if !input.is_pressed(cpu.V[x as usize]) {
+                skip_next(cpu);
+            }
+        }
+        Instruction::Skp2(x) => {
+            if input.is_pressed2(cpu.V[x as usize]) {
+                skip_next(cpu);
+            }
+        }
+        Instruction::Sknp2(x) => {
+            if !input.is_pressed2(cpu.V[x as usize]) {
+                skip_next(cpu);
+            }
+        }
+        Instruction::LdVxDt(x) => {
+            cpu.V[x as usize] = cpu.DT;
+        }
+        Instruction::LdKey(x) => {
+            let mut found = false;
+            for key_seek in 0..16 {
+                if input.is_pressed(key_seek) {
+                    cpu.V[x as usize] = key_seek;
+                    found = true;
+                }
+            }
+            if !found {
+                pc_inc = false;
+                outcome = StepOutcome::WaitingForKey;
+            }
+        }
+        Instruction::LdDtVx(x) => {
+            cpu.DT = cpu.V[x as usize];
+        }
+        Instruction::LdStVx(x) => {
+            cpu.ST = cpu.V[x as usize];
+        }
+        Instruction::AddI(x) => {
+            cpu.I = cpu.I.wrapping_add(cpu.V[x as usize] as u32) & cpu.variant.address_mask();
+        }
+        Instruction::LdFont(x) => {
+            cpu.I = (cpu.V[x as usize] as u32) * 5;
+        }
+        Instruction::LdHiFont(x) => {
+            cpu.I = (BIG_HEX_SPRITES_START + (cpu.V[x as usize] & 0xF) as usize * 10) as u32;
+        }
+        Instruction::LdPitch(x) => {
+            cpu.pitch = cpu.V[x as usize];
+        }
+        Instruction::LdBcd(x) => {
+            // Store the hundreds, tens and ones digits of Vx at I, I+1 and I+2
+            let digits = memory_range(cpu, cpu.I, 3)?;
+            let hex_request = cpu.V[x as usize];
+            cpu.memory[digits.start] = hex_request / 100 % 10;
+            cpu.memory[digits.start + 1] = hex_request / 10 % 10;
+            cpu.memory[digits.start + 2] = hex_request % 10;
+        }
+        Instruction::LdStore(x) => {
+            // V0 through Vx inclusive
+            let range = memory_range(cpu, cpu.I, x as usize + 1)?;
+            for reg in 0..=x {
+                cpu.memory[range.start + reg as usize] = cpu.V[reg as usize];
+            }
+            increment_index(cpu, x);
+        }
+        Instruction::LdLoad(x) => {
+            let range = memory_range(cpu, cpu.I, x as usize + 1)?;
+            for reg in 0..=x {
+                cpu.V[reg as usize] = cpu.memory[range.start + reg as usize];
+            }
+            increment_index(cpu, x);
+        }
+        Instruction::LdRplStore(x) => {
+            cpu.rpl[..=x as usize].copy_from_slice(&cpu.V[..=x as usize]);
+        }
+        Instruction::LdRplLoad(x) => {
+            cpu.V[..=x as usize].copy_from_slice(&cpu.rpl[..=x as usize]);
+        }
+        Instruction::Out(x) => {
+            cpu.port = cpu.V[x as usize];
+        }
+        Instruction::In(x) => {
+            // Nothing is connected to the input port
+            cpu.V[x as usize] = 0;
+        }
+        Instruction::Sys(_) | Instruction::Unknown(_) => {}
+    }
+
+    // append PC
+    if pc_inc {
+        cpu.PC = cpu.PC.wrapping_add(2);
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DEFAULT_FOREGROUND;
+    use crate::random::RandomMode;
+
+    struct Machine {
+        cpu: CPU,
+        frame_buffer: FrameBuffer,
+        input: InputMap,
+    }
+
+    fn machine_for(variant: Variant, program: &[u16]) -> Machine {
+        let mut bytes = Vec::new();
+        for opcode in program {
+            bytes.push((opcode >> 8) as u8);
+            bytes.push(*opcode as u8);
+        }
+        Machine {
+            cpu: init_cpu(&Rom::from_bytes(bytes).unwrap(), variant).unwrap(),
+            frame_buffer: FrameBuffer::for_variant(variant),
+            input: InputMap::new(),
+        }
+    }
+
+    fn machine(program: &[u16]) -> Machine {
+        machine_for(Variant::Chip8, program)
+    }
+
+    impl Machine {
+        fn step(&mut self) {
+            run(&mut self.cpu, &mut self.frame_buffer, &mut self.input).unwrap();
+        }
+
+        // Set the registers, run a single register-register instruction and
+        // return (Vx, VF).
+        fn alu(&mut self, x: usize, vx: u8, y: usize, vy: u8) -> (u8, u8) {
+            self.cpu.V[x] = vx;
+            self.cpu.V[y] = vy;
+            self.step();
+            (self.cpu.V[x], self.cpu.V[0xF])
+        }
+    }
+
+    fn alu(opcode: u16, vx: u8, vy: u8) -> (u8, u8) {
+        machine(&[opcode]).alu(1, vx, 2, vy)
+    }
+
+    #[test]
+    fn add_reg_sets_carry() {
+        assert_eq!(alu(0x8124, 0x10, 0x20), (0x30, 0));
+        assert_eq!(alu(0x8124, 0xFF, 0x01), (0x00, 1));
+        assert_eq!(alu(0x8124, 0xFF, 0xFF), (0xFE, 1));
+        assert_eq!(alu(0x8124, 0x80, 0x7F), (0xFF, 0));
+    }
+
+    #[test]
+    fn add_reg_clears_stale_carry() {
+        let mut machine = machine(&[0x8124]);
+        machine.cpu.V[0xF] = 1;
+        assert_eq!(machine.alu(1, 0x01, 2, 0x01), (0x02, 0));
+    }
+
+    #[test]
+    fn add_reg_flag_overwrites_vf_destination() {
+        let mut destination = machine(&[0x8F14]);
+        destination.cpu.V[0xF] = 0xFF;
+        destination.cpu.V[1] = 0x02;
+        destination.step();
+        assert_eq!(destination.cpu.V[0xF], 1);
+
+        // VF as the source operand is read before the flag is written
+        let mut source = machine(&[0x81F4]);
+        assert_eq!(source.alu(1, 0x01, 0xF, 0xFF), (0x00, 1));
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        assert_eq!(alu(0x8125, 0x30, 0x10), (0x20, 1));
+        assert_eq!(alu(0x8125, 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(0x8125, 0x10, 0x30), (0xE0, 0));
+        assert_eq!(alu(0x8125, 0x00, 0xFF), (0x01, 0));
+    }
+
+    #[test]
+    fn sub_flag_overwrites_vf_destination() {
+        let mut machine = machine(&[0x8F15]);
+        machine.cpu.V[0xF] = 0x05;
+        machine.cpu.V[1] = 0x10;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 0);
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        assert_eq!(alu(0x8127, 0x10, 0x30), (0x20, 1));
+        assert_eq!(alu(0x8127, 0x10, 0x10), (0x00, 1));
+        assert_eq!(alu(0x8127, 0x30, 0x10), (0xE0, 0));
+        assert_eq!(alu(0x8127, 0xFF, 0x00), (0x01, 0));
+    }
+
+    #[test]
+    fn subn_flag_overwrites_vf_destination() {
+        let mut machine = machine(&[0x8F17]);
+        machine.cpu.V[0xF] = 0x20;
+        machine.cpu.V[1] = 0x10;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 0);
+    }
+
+    #[test]
+    fn shr_shifts_by_one() {
+        assert_eq!(alu(0x8126, 0x04, 0x00), (0x02, 0));
+        assert_eq!(alu(0x8126, 0x05, 0x00), (0x02, 1));
+        assert_eq!(alu(0x8126, 0xFF, 0x00), (0x7F, 1));
+        assert_eq!(alu(0x8126, 0x01, 0x00), (0x00, 1));
+    }
+
+    #[test]
+    fn shr_flag_overwrites_vf_destination() {
+        let mut machine = machine(&[0x8F06]);
+        machine.cpu.V[0xF] = 0x02;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 0);
+    }
+
+    #[test]
+    fn shl_shifts_by_one() {
+        assert_eq!(alu(0x812E, 0x40, 0x00), (0x80, 0));
+        assert_eq!(alu(0x812E, 0x80, 0x00), (0x00, 1));
+        assert_eq!(alu(0x812E, 0xFF, 0x00), (0xFE, 1));
+        assert_eq!(alu(0x812E, 0x01, 0x00), (0x02, 0));
+    }
+
+    #[test]
+    fn shl_flag_overwrites_vf_destination() {
+        let mut machine = machine(&[0x8F0E]);
+        machine.cpu.V[0xF] = 0x81;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 1);
+    }
+
+    #[test]
+    fn add_byte_wraps_without_touching_vf() {
+        let mut machine = machine(&[0x71FF]);
+        machine.cpu.V[1] = 0x02;
+        machine.step();
+        assert_eq!(machine.cpu.V[1], 0x01);
+        assert_eq!(machine.cpu.V[0xF], 0);
+    }
+
+    #[test]
+    fn rnd_is_masked() {
+        for _ in 0..64 {
+            let mut machine = machine(&[0xC10F, 0xC200]);
+            machine.step();
+            machine.step();
+            assert_eq!(machine.cpu.V[1] & 0xF0, 0);
+            assert_eq!(machine.cpu.V[2], 0);
+        }
+    }
+
+    #[test]
+    fn rnd_repeats_with_seed() {
+        let program = [0xC0FF, 0xC1FF, 0xC2FF, 0xC3FF];
+        let mut first = machine(&program);
+        let mut second = machine(&program);
+        first.cpu.rng = Random::new(42, RandomMode::Splitmix);
+        second.cpu.rng = Random::new(42, RandomMode::Splitmix);
+        for _ in 0..4 {
+            first.step();
+            second.step();
+        }
+        assert_eq!(first.cpu.V[..4], second.cpu.V[..4]);
+        assert_ne!(first.cpu.V[..4], [first.cpu.V[0]; 4]);
+    }
+
+    #[test]
+    fn store_includes_vx() {
+        let mut machine = machine(&[0xF255]);
+        machine.cpu.V[0] = 0x11;
+        machine.cpu.V[1] = 0x22;
+        machine.cpu.V[2] = 0x33;
+        machine.cpu.V[3] = 0x44;
+        machine.cpu.I = 0x300;
+        machine.step();
+        assert_eq!(&machine.cpu.memory[0x300..0x304], &[0x11, 0x22, 0x33, 0x00]);
+    }
+
+    #[test]
+    fn store_v0_only() {
+        let mut machine = machine(&[0xF055]);
+        machine.cpu.V[0] = 0x11;
+        machine.cpu.V[1] = 0x22;
+        machine.cpu.I = 0x300;
+        machine.step();
+        assert_eq!(&machine.cpu.memory[0x300..0x302], &[0x11, 0x00]);
+    }
+
+    #[test]
+    fn load_includes_vx() {
+        let mut machine = machine(&[0xF265]);
+        machine.cpu.memory[0x300..0x304].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        machine.cpu.I = 0x300;
+        machine.step();
+        assert_eq!(&machine.cpu.V[0..4], &[0x11, 0x22, 0x33, 0x00]);
+    }
+
+    #[test]
+    fn load_all_registers_at_end_of_memory() {
+        let mut machine = machine(&[0xFF65]);
+        machine.cpu.memory[MEMORY_SIZE - 16..].copy_from_slice(&[0xAB; 16]);
+        machine.cpu.I = (MEMORY_SIZE - 16) as u32;
+        machine.step();
+        assert_eq!(machine.cpu.V, [0xAB; 16]);
+    }
+
+    #[test]
+    fn drw_reports_collision_in_vf() {
+        // Draw the "0" glyph twice at the bottom right corner, using VF as
+        // the x coordinate.
+        let mut machine = machine(&[0xDF15, 0xDF15]);
+        machine.cpu.V[0xF] = 62;
+        machine.cpu.V[1] = 30;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 0);
+        assert!(machine.frame_buffer.get(62, 30) && machine.frame_buffer.get(63, 30) && machine.frame_buffer.get(62, 31));
+
+        machine.cpu.V[0xF] = 62;
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 1);
+        assert!(!machine.frame_buffer.get(62, 30) && !machine.frame_buffer.get(63, 30) && !machine.frame_buffer.get(62, 31));
+    }
+
+    #[test]
+    fn shift_quirk_reads_vy() {
+        let mut machine = machine(&[0x8126, 0x812E]);
+        machine.cpu.quirks.shift_uses_vy = true;
+        assert_eq!(machine.alu(1, 0x00, 2, 0x03), (0x01, 1));
+        assert_eq!(machine.alu(1, 0x00, 2, 0x81), (0x02, 1));
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        let mut plain = machine(&[0xB300]);
+        plain.cpu.V[0] = 0x04;
+        plain.cpu.V[3] = 0x10;
+        plain.step();
+        assert_eq!(plain.cpu.PC, 0x304);
+
+        let mut quirky = machine(&[0xB300]);
+        quirky.cpu.quirks.jump_uses_vx = true;
+        quirky.cpu.V[0] = 0x04;
+        quirky.cpu.V[3] = 0x10;
+        quirky.step();
+        assert_eq!(quirky.cpu.PC, 0x310);
+    }
+
+    #[test]
+    fn load_store_index_increment_quirk() {
+        for &(increment, expected) in &[(IndexIncrement::Unchanged, 0x300), (IndexIncrement::ByX, 0x302), (IndexIncrement::ByXPlusOne, 0x303)] {
+            let mut machine = machine(&[0xF255, 0xF265]);
+            machine.cpu.quirks.index_increment = increment;
+            machine.cpu.I = 0x300;
+            machine.step();
+            assert_eq!(machine.cpu.I, expected);
+            machine.cpu.I = 0x300;
+            machine.step();
+            assert_eq!(machine.cpu.I, expected);
+        }
+    }
+
+    #[test]
+    fn logic_vf_reset_quirk() {
+        for &opcode in &[0x8121, 0x8122, 0x8123] {
+            let mut machine = machine(&[opcode, opcode]);
+            machine.cpu.V[0xF] = 0x07;
+            machine.step();
+            assert_eq!(machine.cpu.V[0xF], 0x07);
+
+            machine.cpu.quirks.logic_resets_vf = true;
+            machine.step();
+            assert_eq!(machine.cpu.V[0xF], 0);
+        }
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        let mut machine = machine(&[0xD005, 0xD005]);
+        assert_eq!(run(&mut machine.cpu, &mut machine.frame_buffer, &mut machine.input), Ok(StepOutcome::Executed));
+        machine.cpu.quirks.display_wait = true;
+        assert_eq!(run(&mut machine.cpu, &mut machine.frame_buffer, &mut machine.input), Ok(StepOutcome::WaitForFrame));
+    }
+
+    fn schip(program: &[u16]) -> Machine {
+        machine_for(Variant::SuperChip, program)
+    }
+
+    #[test]
+    fn schip_resolution_switch() {
+        let mut machine = schip(&[0x00FF, 0xD010, 0x00FE]);
+        machine.step();
+        assert_eq!((machine.frame_buffer.width(), machine.frame_buffer.height()), (HIRES_WIDTH, HIRES_HEIGHT));
+        machine.cpu.V[1] = 100;
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        machine.step();
+        assert!(machine.frame_buffer.get(0, 100 - HIRES_HEIGHT) && machine.frame_buffer.get(15, 100 - HIRES_HEIGHT + 15));
+        machine.step();
+        assert_eq!((machine.frame_buffer.width(), machine.frame_buffer.height()), (WIDTH, HEIGHT));
+    }
+
+    #[test]
+    fn schip_instructions_ignored_on_chip8() {
+        let mut machine = machine(&[0x00FF, 0xD010, 0xF030, 0x00FD]);
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        for _ in 0..3 {
+            machine.step();
+        }
+        assert_eq!(machine.frame_buffer, FrameBuffer::default());
+        assert_eq!(machine.cpu.I, 0x300);
+        assert_eq!(run(&mut machine.cpu, &mut machine.frame_buffer, &mut machine.input), Ok(StepOutcome::Executed));
+    }
+
+    #[test]
+    fn schip_exit_stays_on_instruction() {
+        let mut machine = schip(&[0x00FD]);
+        assert_eq!(run(&mut machine.cpu, &mut machine.frame_buffer, &mut machine.input), Ok(StepOutcome::Exited));
+        assert_eq!(machine.cpu.PC, 0x200);
+    }
+
+    #[test]
+    fn schip_large_font() {
+        let mut machine = schip(&[0xF130]);
+        machine.cpu.V[1] = 0x0B;
+        machine.step();
+        let glyph = machine.cpu.I as usize;
+        assert_eq!(&machine.cpu.memory[glyph..glyph + 10], &[0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC]);
+    }
+
+    #[test]
+    fn schip_rpl_flags() {
+        let mut machine = schip(&[0xF275, 0x6000, 0x6100, 0x6200, 0xF185]);
+        machine.cpu.V[0..4].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        for _ in 0..5 {
+            machine.step();
+        }
+        assert_eq!(&machine.cpu.rpl[0..4], &[0x11, 0x22, 0x33, 0x00]);
+        assert_eq!(&machine.cpu.V[0..4], &[0x11, 0x22, 0x00, 0x44]);
+    }
+
+    fn xochip(program: &[u16]) -> Machine {
+        machine_for(Variant::XoChip, program)
+    }
+
+    #[test]
+    fn rom_must_fit_variant_memory() {
+        let rom = Rom::from_bytes(vec![0; MEMORY_SIZE]).unwrap();
+        assert!(matches!(init_cpu(&rom, Variant::Chip8), Err(RomError::TooLarge { size: MEMORY_SIZE, max }) if max == MEMORY_SIZE - PROGRAM_START));
+        assert_eq!(init_cpu(&rom, Variant::XoChip).unwrap().memory.len(), XO_MEMORY_SIZE);
+    }
+
+    #[test]
+    fn xochip_long_index_load() {
+        let mut machine = xochip(&[0xF000, 0xE123, 0x3000, 0xF000, 0xFFFF, 0x00E0]);
+        machine.step();
+        assert_eq!((machine.cpu.I, machine.cpu.PC), (0xE123, 0x204));
+        // A skip steps over both words
+        machine.step();
+        assert_eq!(machine.cpu.PC, 0x20A);
+        machine.cpu.I = 0xFFF0;
+        machine.cpu.memory[0xFFF0..].copy_from_slice(&[0xFF; 16]);
+        machine.cpu.V[0] = 0xFF;
+        machine.step();
+        assert_eq!(machine.cpu.V[0], 0xFF);
+    }
+
+    #[test]
+    fn long_index_load_ignored_on_schip() {
+        let mut machine = schip(&[0xF000, 0x0300]);
+        machine.step();
+        assert_eq!((machine.cpu.I, machine.cpu.PC), (0, 0x202));
+    }
+
+    #[test]
+    fn xochip_register_ranges() {
+        let mut machine = xochip(&[0x5132, 0x5313, 0x5233]);
+        machine.cpu.V[1..4].copy_from_slice(&[0x11, 0x22, 0x33]);
+        machine.cpu.I = 0x300;
+        machine.step();
+        assert_eq!(&machine.cpu.memory[0x300..0x304], &[0x11, 0x22, 0x33, 0x00]);
+        // Reversed ranges load in descending register order
+        machine.step();
+        assert_eq!(&machine.cpu.V[1..4], &[0x33, 0x22, 0x11]);
+        assert_eq!(machine.cpu.I, 0x300);
+        machine.step();
+        assert_eq!(&machine.cpu.V[1..4], &[0x33, 0x11, 0x22]);
+    }
+
+    #[test]
+    fn xochip_planes_draw_sprite_per_plane() {
+        let mut machine = xochip(&[0xF301, 0xD001]);
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x302].copy_from_slice(&[0x80, 0xC0]);
+        machine.step();
+        machine.step();
+        assert_eq!((machine.frame_buffer.pixel(0, 0), machine.frame_buffer.pixel(1, 0)), (3, 2));
+    }
+
+    #[test]
+    fn xochip_audio_pattern_and_pitch() {
+        let mut machine = xochip(&[0xF002, 0xF13A]);
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        machine.cpu.V[1] = 112;
+        assert_eq!(machine.cpu.audio_pattern, None);
+        machine.step();
+        machine.step();
+        assert_eq!(machine.cpu.audio_pattern, Some([0xAA; 16]));
+        assert_eq!(machine.cpu.pitch, 112);
+    }
+
+    fn megachip(program: &[u16]) -> Machine {
+        machine_for(Variant::MegaChip, program)
+    }
+
+    #[test]
+    fn megachip_opcodes_are_sys_elsewhere() {
+        let mut machine = schip(&[0x0011, 0x0230]);
+        machine.step();
+        machine.step();
+        assert!(!machine.frame_buffer.is_mega());
+        assert_eq!(decode(Variant::SuperChip, 0x0230, 0), Instruction::Sys(0x230));
+    }
+
+    #[test]
+    fn megachip_huge_index_load() {
+        let mut machine = megachip(&[0x0112, 0x3456, 0x00E0]);
+        machine.step();
+        assert_eq!((machine.cpu.I, machine.cpu.PC), (0x12_3456, 0x204));
+    }
+
+    #[test]
+    fn megachip_sprite_uses_palette_and_collision_colour() {
+        // MEGAON, LDPAL 2, SPRW 2, SPRH 1, CCOL 1, DRW twice, CLS
+        let mut machine = megachip(&[0x0011, 0x0202, 0x0302, 0x0401, 0x0901, 0xD010, 0xD010, 0x00E0]);
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x308].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00]);
+        for _ in 0..5 {
+            machine.step();
+        }
+        assert!(machine.frame_buffer.is_mega());
+        assert_eq!(&machine.cpu.megachip.palette[1..3], &[0xFFFF_0000, 0xFF00_FF00]);
+
+        machine.cpu.memory[0x300..0x302].copy_from_slice(&[1, 2]);
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 0);
+        assert_eq!((machine.frame_buffer.pixel(0, 0), machine.frame_buffer.pixel(1, 0)), (1, 2));
+        machine.step();
+        assert_eq!(machine.cpu.V[0xF], 1);
+
+        // The finished frame stays visible after the clear
+        assert_eq!(run(&mut machine.cpu, &mut machine.frame_buffer, &mut machine.input), Ok(StepOutcome::WaitForFrame));
+        assert_eq!(machine.frame_buffer.pixel(0, 0), 0);
+        assert_eq!(machine.frame_buffer.shown_argb(0, 0), 0xFFFF_0000);
+    }
+
+    #[test]
+    fn megachip_sample() {
+        let mut machine = megachip(&[0x0601, 0x0700]);
+        machine.cpu.I = 0x300;
+        machine.cpu.memory[0x300..0x309].copy_from_slice(&[0x1F, 0x40, 0x00, 0x00, 0x03, 0x00, 0x80, 0xFF, 0x00]);
+        machine.step();
+        let sample = machine.cpu.megachip.sample.clone().unwrap();
+        assert_eq!(*sample, Sample { rate: 8000, data: vec![0x80, 0xFF, 0x00], looping: false });
+        machine.step();
+        assert_eq!(machine.cpu.megachip.sample, None);
+    }
+
+    #[test]
+    fn chip8_hires_display_and_clear() {
+        let mut hires = machine_for(Variant::Chip8Hires, &[0x0230]);
+        assert_eq!((hires.frame_buffer.width(), hires.frame_buffer.height()), (WIDTH, 2 * HEIGHT));
+        hires.frame_buffer.set(5, 60, true);
+        hires.step();
+        assert!(!hires.frame_buffer.get(5, 60));
+
+        // 0230 is a machine code call on the plain VIP interpreter
+        let mut plain = machine(&[0x0230]);
+        plain.frame_buffer.set(5, 5, true);
+        plain.step();
+        assert!(plain.frame_buffer.get(5, 5));
+    }
+
+    #[test]
+    fn chip8_hires_skips_the_patch() {
+        let machine = machine_for(Variant::Chip8Hires, &[0x1260]);
+        assert_eq!(machine.cpu.PC, 0x2C0);
+    }
+
+    #[test]
+    fn chip8x_starts_at_0x300() {
+        let machine = machine_for(Variant::Chip8X, &[0x00E0]);
+        assert_eq!(machine.cpu.PC, 0x300);
+        assert_eq!(&machine.cpu.memory[0x300..0x302], &[0x00, 0xE0]);
+    }
+
+    #[test]
+    fn chip8x_color_zones() {
+        let mut machine = machine_for(Variant::Chip8X, &[0xB123, 0xB340, 0x02A0]);
+        machine.cpu.V[1] = 10;
+        machine.cpu.V[2] = 5;
+        machine.cpu.V[3] = 0x11;
+        machine.cpu.V[4] = 0x02;
+        machine.step();
+        assert_eq!(machine.frame_buffer.foreground(8, 5), 5);
+        assert_eq!(machine.frame_buffer.foreground(15, 7), 5);
+        assert_eq!(machine.frame_buffer.foreground(16, 5), DEFAULT_FOREGROUND);
+        assert_eq!(machine.frame_buffer.foreground(8, 8), DEFAULT_FOREGROUND);
+
+        // Zone columns 1-2 and row 0 (pixel rows 0-3), in colour V4
+        machine.cpu.V[4] = 0x00;
+        machine.step();
+        assert_eq!(machine.frame_buffer.foreground(8, 0), 0);
+        assert_eq!(machine.frame_buffer.foreground(23, 3), 0);
+        assert_eq!(machine.frame_buffer.foreground(24, 3), DEFAULT_FOREGROUND);
+        assert_eq!(machine.frame_buffer.foreground(8, 4), DEFAULT_FOREGROUND);
+
+        machine.step();
+        assert_eq!(machine.frame_buffer.background(), 1);
+    }
+
+    #[test]
+    fn chip8x_add_nibbles_and_second_keypad() {
+        let mut machine = machine_for(Variant::Chip8X, &[0x5121, 0xE1F2, 0x0000, 0xE1F5]);
+        machine.cpu.V[1] = 0x36;
+        machine.cpu.V[2] = 0x57;
+        machine.step();
+        assert_eq!(machine.cpu.V[1], 0x05);
+        machine.input.set_key(5, true);
+        machine.input.set_key2(5, true);
+        machine.step();
+        assert_eq!(machine.cpu.PC, 0x306);
+        machine.input.set_key2(5, false);
+        machine.step();
+        assert_eq!(machine.cpu.PC, 0x30A);
+    }
+
+    impl Machine {
+        // Run an instruction that must fault, and check that it left the
+        // machine as it was.
+        fn fault(&mut self) -> CpuFault {
+            let (pc, sp, i, v, stack, rng) = (self.cpu.PC, self.cpu.SP, self.cpu.I, self.cpu.V, self.cpu.S, self.cpu.rng);
+            let memory = self.cpu.memory.clone();
+            let display = self.frame_buffer.to_text();
+            let fault = run(&mut self.cpu, &mut self.frame_buffer, &mut self.input).unwrap_err();
+            assert_eq!((self.cpu.PC, self.cpu.SP, self.cpu.I), (pc, sp, i));
+            assert_eq!(self.cpu.V, v);
+            assert_eq!(self.cpu.S, stack);
+            assert_eq!(self.cpu.rng, rng);
+            assert!(self.cpu.memory == memory, "memory changed");
+            assert_eq!(self.frame_buffer.to_text(), display);
+            fault
+        }
+    }
+
+    fn out_of_bounds(pc: u16, opcode: u16, addr: usize) -> CpuFault {
+        CpuFault { kind: FaultKind::MemoryOutOfBounds { addr }, pc, opcode }
+    }
+
+    #[test]
+    fn call_faults_when_the_stack_is_full() {
+        // 200: CALL 200
+        let mut machine = machine(&[0x2200]);
+        for _ in 0..16 {
+            machine.step();
+        }
+        assert_eq!(machine.cpu.SP, 16);
+        assert_eq!(machine.fault(), CpuFault { kind: FaultKind::StackOverflow, pc: 0x200, opcode: 0x2200 });
+    }
+
+    #[test]
+    fn ret_faults_when_the_stack_is_empty() {
+        let mut machine = machine(&[0x00EE]);
+        assert_eq!(machine.fault(), CpuFault { kind: FaultKind::StackUnderflow, pc: 0x200, opcode: 0x00EE });
+    }
+
+    #[test]
+    fn fetch_faults_past_the_end_of_memory() {
+        let mut machine = machine(&[0x1FFF]);
+        machine.step();
+        assert_eq!(machine.cpu.PC, 0xFFF);
+        assert_eq!(machine.fault(), CpuFault { kind: FaultKind::PcOutOfBounds, pc: 0xFFF, opcode: 0 });
+    }
+
+    #[test]
+    fn memory_instructions_fault_past_the_end_of_memory() {
+        let faulting = |opcode: u16, i: u32| {
+            let mut machine = machine(&[opcode]);
+            machine.cpu.V = [0xA5; 16];
+            machine.cpu.I = i;
+            machine.fault()
+        };
+        assert_eq!(faulting(0xF355, 0xFFE), out_of_bounds(0x200, 0xF355, 0x1000));
+        assert_eq!(faulting(0xF365, 0xFFD), out_of_bounds(0x200, 0xF365, 0x1000));
+        assert_eq!(faulting(0xF033, 0xFFE), out_of_bounds(0x200, 0xF033, 0x1000));
+        assert_eq!(faulting(0xD015, 0xFFC), out_of_bounds(0x200, 0xD015, 0x1000));
+        // Transfers that end exactly at the end of memory are fine
+        let mut machine = machine(&[0xF355]);
+        machine.cpu.I = 0xFFC;
+        machine.step();
+        assert_eq!(machine.cpu.PC, 0x202);
+    }
+}
+
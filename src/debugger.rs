@@ -0,0 +1,659 @@
+// Interactive debugger: runs a machine one instruction at a time through
+// `Chip8::step`, stopping at breakpoints, watchpoints and conditions.
+//
+// `Debugger` holds the breakpoints and does the stepping, `run_console`
+// drives it from text commands, e.g. on a terminal.
+
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+use crate::chip8::{Chip8, TIMER_HZ};
+use crate::cpu;
+use crate::fault::{CpuFault, StepOutcome};
+use crate::instruction::Instruction;
+
+// How many instructions `continue`, `until` and `next` run before giving up,
+// so a program that never gets there doesn't hang the debugger. About four
+// hours of emulated time at the default speed.
+pub const DEFAULT_RUN_LIMIT: u64 = 10_000_000;
+
+// Something a condition or watchpoint looks at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    V(u8),
+    I,
+    PC,
+    SP,
+    DT,
+    ST,
+    // A byte of memory.
+    Memory(u32),
+}
+
+impl Target {
+    pub fn parse(text: &str) -> Option<Target> {
+        let upper = text.to_ascii_uppercase();
+        match upper.as_str() {
+            "I" => return Some(Target::I),
+            "PC" => return Some(Target::PC),
+            "SP" => return Some(Target::SP),
+            "DT" => return Some(Target::DT),
+            "ST" => return Some(Target::ST),
+            _ => {}
+        }
+        if let Some(reg) = upper.strip_prefix('V') {
+            return match u8::from_str_radix(reg, 16) {
+                Ok(reg) if reg < 16 => Some(Target::V(reg)),
+                _ => None,
+            };
+        }
+        let addr = text.strip_prefix('[')?.strip_suffix(']')?;
+        parse_number(addr).map(Target::Memory)
+    }
+
+    // Current value, memory outside of RAM reads as zero.
+    pub fn read(&self, chip8: &Chip8) -> u32 {
+        let cpu = &chip8.cpu;
+        match *self {
+            Target::V(reg) => cpu.V[reg as usize] as u32,
+            Target::I => cpu.I,
+            Target::PC => cpu.PC as u32,
+            Target::SP => cpu.SP as u32,
+            Target::DT => cpu.DT as u32,
+            Target::ST => cpu.ST as u32,
+            Target::Memory(addr) => cpu.memory.get(addr as usize).copied().unwrap_or(0) as u32,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::V(reg) => write!(f, "V{:X}", reg),
+            Target::I => write!(f, "I"),
+            Target::PC => write!(f, "PC"),
+            Target::SP => write!(f, "SP"),
+            Target::DT => write!(f, "DT"),
+            Target::ST => write!(f, "ST"),
+            Target::Memory(addr) => write!(f, "[#{:03X}]", addr),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compare {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+const COMPARISONS: [(&str, Compare); 6] = [
+    ("==", Compare::Equal),
+    ("!=", Compare::NotEqual),
+    ("<=", Compare::LessOrEqual),
+    (">=", Compare::GreaterOrEqual),
+    ("<", Compare::Less),
+    (">", Compare::Greater),
+];
+
+// A comparison such as `V3 == 0x10` or `[0x300] != 0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub target: Target,
+    pub compare: Compare,
+    pub value: u32,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Option<Condition> {
+        let (operator, compare) = COMPARISONS.iter().find(|(operator, _)| text.contains(operator))?;
+        let mut parts = text.splitn(2, operator);
+        let target = Target::parse(parts.next()?.trim())?;
+        let value = parse_number(parts.next()?.trim())?;
+        Some(Condition { target, compare: *compare, value })
+    }
+
+    pub fn holds(&self, chip8: &Chip8) -> bool {
+        let current = self.target.read(chip8);
+        match self.compare {
+            Compare::Equal => current == self.value,
+            Compare::NotEqual => current != self.value,
+            Compare::Less => current < self.value,
+            Compare::LessOrEqual => current <= self.value,
+            Compare::Greater => current > self.value,
+            Compare::GreaterOrEqual => current >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = COMPARISONS.iter().find(|(_, compare)| *compare == self.compare).map_or("?", |(operator, _)| operator);
+        write!(f, "{} {} #{:X}", self.target, operator, self.value)
+    }
+}
+
+// Numbers are decimal unless they start with 0x or #.
+pub fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).or_else(|| text.strip_prefix('#')) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Stops when execution reaches `pc`, if given, and the condition holds.
+// Without an address the condition is checked after every instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub pc: Option<u16>,
+    pub condition: Option<Condition>,
+}
+
+// Stops when the value of the target changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub target: Target,
+    // Value when last checked.
+    value: u32,
+}
+
+// Why execution stopped.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Stop {
+    // The requested instructions were executed.
+    Done,
+    Breakpoint(usize),
+    Watchpoint { id: usize, old: u32, new: u32 },
+    // PC didn't move: a jump to itself or Fx0A waiting for a key.
+    Waiting,
+    Exited,
+    Fault(CpuFault),
+    // The run limit was reached first.
+    Limit(u64),
+}
+
+enum Point {
+    Break(Breakpoint),
+    Watch(Watchpoint),
+}
+
+pub struct Debugger {
+    // Breakpoints and watchpoints share their numbering, deleted ones are
+    // None so the numbers stay the same.
+    points: Vec<Option<Point>>,
+    // Instructions executed, to update the timers at the machine's speed.
+    cycles: u64,
+    // Most instructions a single run executes, see `DEFAULT_RUN_LIMIT`.
+    pub run_limit: u64,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger { points: Vec::new(), cycles: 0, run_limit: DEFAULT_RUN_LIMIT }
+    }
+
+    // Returns the number identifying the breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> usize {
+        self.points.push(Some(Point::Break(breakpoint)));
+        self.points.len()
+    }
+
+    // Returns the number identifying the watchpoint.
+    pub fn add_watchpoint(&mut self, target: Target, chip8: &Chip8) -> usize {
+        self.points.push(Some(Point::Watch(Watchpoint { target, value: target.read(chip8) })));
+        self.points.len()
+    }
+
+    // Remove a breakpoint or watchpoint, false if there is no such number.
+    pub fn delete(&mut self, id: usize) -> bool {
+        match self.points.get_mut(id.wrapping_sub(1)) {
+            Some(point) => point.take().is_some(),
+            None => false,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, &Breakpoint)> {
+        self.points.iter().enumerate().filter_map(|(index, point)| match point {
+            Some(Point::Break(breakpoint)) => Some((index + 1, breakpoint)),
+            _ => None,
+        })
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.points.iter().enumerate().filter_map(|(index, point)| match point {
+            Some(Point::Watch(watchpoint)) => Some((index + 1, watchpoint)),
+            _ => None,
+        })
+    }
+
+    // Execute one instruction, updating the timers every 1/60th of a second
+    // of emulated time.
+    fn execute(&mut self, chip8: &mut Chip8) -> Option<Stop> {
+        let outcome = match chip8.step() {
+            Ok(outcome) => outcome,
+            Err(fault) => return Some(Stop::Fault(fault)),
+        };
+        self.cycles += 1;
+        if self.cycles.is_multiple_of((chip8.ips / TIMER_HZ).max(1) as u64) {
+            chip8.tick_timers();
+        }
+        if outcome == StepOutcome::Exited {
+            return Some(Stop::Exited);
+        }
+        None
+    }
+
+    // Check the watchpoints and breakpoints after an instruction.
+    fn check(&mut self, chip8: &Chip8) -> Option<Stop> {
+        let mut stop = None;
+        for (index, point) in self.points.iter_mut().enumerate() {
+            match point {
+                Some(Point::Watch(watchpoint)) => {
+                    let new = watchpoint.target.read(chip8);
+                    if new != watchpoint.value {
+                        let old = watchpoint.value;
+                        watchpoint.value = new;
+                        stop = stop.or(Some(Stop::Watchpoint { id: index + 1, old, new }));
+                    }
+                }
+                Some(Point::Break(breakpoint)) => {
+                    let at_pc = breakpoint.pc.is_none_or(|pc| pc == chip8.cpu.PC);
+                    if at_pc && breakpoint.condition.is_none_or(|condition| condition.holds(chip8)) {
+                        stop = stop.or(Some(Stop::Breakpoint(index + 1)));
+                    }
+                }
+                None => {}
+            }
+        }
+        stop
+    }
+
+    // Execute `count` instructions, stopping early only if the machine can't
+    // go on. Watchpoints are updated but don't stop single steps.
+    pub fn step(&mut self, chip8: &mut Chip8, count: u32) -> Stop {
+        for _ in 0..count {
+            if let Some(stop) = self.execute(chip8) {
+                return stop;
+            }
+            self.check(chip8);
+        }
+        Stop::Done
+    }
+
+    // Run until `done` returns true after an instruction, or a breakpoint,
+    // watchpoint, fault, exit or the run limit stops execution first.
+    fn run_until<F: Fn(&Chip8) -> bool>(&mut self, chip8: &mut Chip8, done: F) -> Stop {
+        for _ in 0..self.run_limit {
+            let pc = chip8.cpu.PC;
+            if let Some(stop) = self.execute(chip8) {
+                return stop;
+            }
+            if let Some(stop) = self.check(chip8) {
+                return stop;
+            }
+            if done(chip8) {
+                return Stop::Done;
+            }
+            if chip8.cpu.PC == pc {
+                return Stop::Waiting;
+            }
+        }
+        Stop::Limit(self.run_limit)
+    }
+
+    // Like `step`, but a CALL runs until the subroutine returns.
+    pub fn step_over(&mut self, chip8: &mut Chip8) -> Stop {
+        match cpu::decode_at(&chip8.cpu, chip8.cpu.PC) {
+            Some((_, Instruction::Call(_), len)) => {
+                let return_pc = chip8.cpu.PC.wrapping_add(len);
+                let sp = chip8.cpu.SP;
+                self.run_until(chip8, |chip8| chip8.cpu.PC == return_pc && chip8.cpu.SP == sp)
+            }
+            _ => self.step(chip8, 1),
+        }
+    }
+
+    // Run until something stops execution.
+    pub fn resume(&mut self, chip8: &mut Chip8) -> Stop {
+        self.run_until(chip8, |_| false)
+    }
+
+    // Run until PC reaches `pc`.
+    pub fn run_to(&mut self, chip8: &mut Chip8, pc: u16) -> Stop {
+        self.run_until(chip8, |chip8| chip8.cpu.PC == pc)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+const HELP: &str = "commands:
+    s, step [N]           execute N instructions (default 1)
+    n, next               step over a CALL
+    c, continue           run until a breakpoint, watchpoint or fault
+    u, until ADDR         run until PC reaches ADDR
+    limit N               stop continue, until and next after N instructions
+    b, break ADDR [if C]  stop at ADDR, when the condition C holds if given
+    b, break if C         stop as soon as the condition C holds
+    w, watch T            stop when T changes
+    d, delete N           remove breakpoint or watchpoint N
+    l, list               show breakpoints and watchpoints
+    r, regs               show the registers
+    m, mem ADDR [LEN]     show LEN bytes of memory (default 16)
+    screen                show the display
+    key K on|off          press or release keypad key K
+    q, quit               stop debugging
+
+T is a register (V0-VF, I, PC, SP, DT, ST) or a memory byte [ADDR], a
+condition C compares one with a number, e.g. `V3 == 0x10` or `[#300] != 0`.
+Numbers are decimal, or hexadecimal after 0x or #. An empty line repeats
+the last command.";
+
+fn write_location<W: Write>(chip8: &Chip8, out: &mut W) -> io::Result<()> {
+    let pc = chip8.cpu.PC;
+    match cpu::decode_at(&chip8.cpu, pc) {
+        Some((opcode, instruction, _)) => writeln!(out, "{:04X}: {:04X}  {}", pc, opcode, instruction),
+        None => writeln!(out, "{:04X}: outside of memory", pc),
+    }
+}
+
+fn write_registers<W: Write>(chip8: &Chip8, out: &mut W) -> io::Result<()> {
+    let cpu = &chip8.cpu;
+    for (reg, value) in cpu.V.iter().enumerate() {
+        write!(out, "V{:X}={:02X}{}", reg, value, if reg == 7 || reg == 15 { "\n" } else { " " })?;
+    }
+    writeln!(out, "I={:04X} PC={:04X} SP={:X} DT={:02X} ST={:02X}", cpu.I, cpu.PC, cpu.SP, cpu.DT, cpu.ST)?;
+    write!(out, "stack:")?;
+    for addr in cpu.S[..cpu.SP as usize].iter() {
+        write!(out, " {:04X}", addr)?;
+    }
+    writeln!(out)
+}
+
+fn write_memory<W: Write>(chip8: &Chip8, start: u32, len: u32, out: &mut W) -> io::Result<()> {
+    let memory = &chip8.cpu.memory;
+    for row in (start..start.saturating_add(len)).step_by(16) {
+        write!(out, "{:04X}:", row)?;
+        for addr in row..(row + 16).min(start.saturating_add(len)) {
+            match memory.get(addr as usize) {
+                Some(byte) => write!(out, " {:02X}", byte)?,
+                None => write!(out, " --")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_stop<W: Write>(debugger: &Debugger, stop: &Stop, out: &mut W) -> io::Result<()> {
+    match stop {
+        Stop::Done => Ok(()),
+        Stop::Breakpoint(id) => writeln!(out, "breakpoint {}", id),
+        Stop::Watchpoint { id, old, new } => {
+            let target = debugger.watchpoints().find(|(watch_id, _)| watch_id == id).map(|(_, watchpoint)| watchpoint.target);
+            match target {
+                Some(target) => writeln!(out, "watchpoint {}: {} changed from #{:X} to #{:X}", id, target, old, new),
+                None => writeln!(out, "watchpoint {}", id),
+            }
+        }
+        Stop::Waiting => writeln!(out, "program is waiting, PC didn't advance"),
+        Stop::Exited => writeln!(out, "program exited"),
+        Stop::Fault(fault) => writeln!(out, "CPU fault: {}", fault),
+        Stop::Limit(count) => writeln!(out, "stopped after {} instructions", count),
+    }
+}
+
+fn parse_breakpoint(args: &str) -> Result<Breakpoint, String> {
+    let (address, condition) = match args.find("if ") {
+        Some(index) => (args[..index].trim(), Some(args[index + 3..].trim())),
+        None => (args.trim(), None),
+    };
+    let pc = if address.is_empty() {
+        None
+    } else {
+        Some(parse_number(address).filter(|&pc| pc <= 0xFFFF).ok_or(format!("invalid address {}", address))? as u16)
+    };
+    let condition = match condition {
+        Some(condition) => Some(Condition::parse(condition).ok_or(format!("invalid condition {}", condition))?),
+        None => None,
+    };
+    if pc.is_none() && condition.is_none() {
+        return Err("break needs an address or a condition".to_string());
+    }
+    Ok(Breakpoint { pc, condition })
+}
+
+// Run one command, returns false once the debugger should quit.
+fn run_command<W: Write>(debugger: &mut Debugger, chip8: &mut Chip8, line: &str, out: &mut W) -> io::Result<bool> {
+    let line = line.trim();
+    let (command, args) = match line.find(' ') {
+        Some(index) => (&line[..index], line[index + 1..].trim()),
+        None => (line, ""),
+    };
+    let number = |text: &str| parse_number(text).ok_or(format!("invalid number {}", text));
+    let result: Result<Option<Stop>, String> = match command {
+        "s" | "step" => {
+            let count = if args.is_empty() { Ok(1) } else { number(args) };
+            count.map(|count| Some(debugger.step(chip8, count)))
+        }
+        "n" | "next" => Ok(Some(debugger.step_over(chip8))),
+        "c" | "continue" => Ok(Some(debugger.resume(chip8))),
+        "u" | "until" => match parse_number(args).filter(|&pc| pc <= 0xFFFF) {
+            Some(pc) => Ok(Some(debugger.run_to(chip8, pc as u16))),
+            None => Err(format!("invalid address {}", args)),
+        },
+        "b" | "break" => parse_breakpoint(args).map(|breakpoint| {
+            let id = debugger.add_breakpoint(breakpoint);
+            let _ = writeln!(out, "breakpoint {}", id);
+            None
+        }),
+        "w" | "watch" => match Target::parse(args) {
+            Some(target) => {
+                let id = debugger.add_watchpoint(target, chip8);
+                writeln!(out, "watchpoint {}: {} = #{:X}", id, target, target.read(chip8))?;
+                Ok(None)
+            }
+            None => Err(format!("invalid watch target {}", args)),
+        },
+        "d" | "delete" => number(args).and_then(|id| {
+            if debugger.delete(id as usize) {
+                Ok(None)
+            } else {
+                Err(format!("no breakpoint or watchpoint {}", id))
+            }
+        }),
+        "l" | "list" => {
+            for (id, breakpoint) in debugger.breakpoints() {
+                match (breakpoint.pc, breakpoint.condition) {
+                    (Some(pc), Some(condition)) => writeln!(out, "{}: break at {:04X} if {}", id, pc, condition)?,
+                    (Some(pc), None) => writeln!(out, "{}: break at {:04X}", id, pc)?,
+                    (None, Some(condition)) => writeln!(out, "{}: break if {}", id, condition)?,
+                    (None, None) => {}
+                }
+            }
+            for (id, watchpoint) in debugger.watchpoints() {
+                writeln!(out, "{}: watch {}", id, watchpoint.target)?;
+            }
+            Ok(None)
+        }
+        "limit" => number(args).map(|count| {
+            debugger.run_limit = count as u64;
+            None
+        }),
+        "r" | "regs" => write_registers(chip8, out).map(|_| None).map_err(|error| error.to_string()),
+        "m" | "mem" => {
+            let mut parts = args.split_whitespace();
+            let start = parts.next().ok_or("mem needs an address".to_string()).and_then(number);
+            let len = parts.next().map_or(Ok(16), number);
+            match (start, len) {
+                (Ok(start), Ok(len)) => write_memory(chip8, start, len, out).map(|_| None).map_err(|error| error.to_string()),
+                (Err(error), _) | (_, Err(error)) => Err(error),
+            }
+        }
+        "screen" => write!(out, "{}", chip8.frame_buffer.to_text()).map(|_| None).map_err(|error| error.to_string()),
+        "key" => {
+            let mut parts = args.split_whitespace();
+            let key = parts.next().and_then(|key| u8::from_str_radix(key, 16).ok()).filter(|&key| key < 16);
+            let pressed = match parts.next() {
+                Some("on") => Some(true),
+                Some("off") => Some(false),
+                _ => None,
+            };
+            match (key, pressed) {
+                (Some(key), Some(pressed)) => {
+                    chip8.input.set_key(key, pressed);
+                    Ok(None)
+                }
+                _ => Err("usage: key K on|off, K is a hex digit".to_string()),
+            }
+        }
+        "q" | "quit" => return Ok(false),
+        "h" | "help" => writeln!(out, "{}", HELP).map(|_| None).map_err(|error| error.to_string()),
+        _ => Err(format!("unknown command {}, try help", command)),
+    };
+    match result {
+        Ok(Some(stop)) => {
+            write_stop(debugger, &stop, out)?;
+            write_location(chip8, out)?;
+        }
+        Ok(None) => {}
+        Err(message) => writeln!(out, "{}", message)?,
+    }
+    Ok(true)
+}
+
+// Read commands from `input` until it ends or `quit`, writing results and a
+// prompt to `out`.
+pub fn run_console<R: BufRead, W: Write>(chip8: &mut Chip8, input: R, out: &mut W) -> io::Result<()> {
+    let mut debugger = Debugger::new();
+    let mut last = String::new();
+    write_location(chip8, out)?;
+    write!(out, "(rchip8) ")?;
+    out.flush()?;
+    for line in input.lines() {
+        let mut line = line?;
+        if line.trim().is_empty() {
+            line = last.clone();
+        }
+        if !run_command(&mut debugger, chip8, &line, out)? {
+            break;
+        }
+        last = line;
+        write!(out, "(rchip8) ")?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    fn machine(program: &[u16]) -> Chip8 {
+        let bytes: Vec<u8> = program.iter().flat_map(|opcode| opcode.to_be_bytes()).collect();
+        Chip8::from_program(&bytes, Variant::Chip8)
+    }
+
+    // 200: V3 += 1; 202: CALL 20A; 204: SE V3, 5; 206: JP 200; 208: JP 208
+    // 20A: LD [I], V0 via I=300; 20C..: RET
+    const PROGRAM: [u16; 9] = [0x7301, 0x220A, 0x3305, 0x1200, 0x1208, 0xA300, 0xF355, 0x00EE, 0x0000];
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!(Condition::parse("V3 == 0x10"), Some(Condition { target: Target::V(3), compare: Compare::Equal, value: 0x10 }));
+        assert_eq!(Condition::parse("[#300]!=0"), Some(Condition { target: Target::Memory(0x300), compare: Compare::NotEqual, value: 0 }));
+        assert_eq!(Condition::parse("dt <= 3"), Some(Condition { target: Target::DT, compare: Compare::LessOrEqual, value: 3 }));
+        assert_eq!(Condition::parse("VG == 1"), None);
+        assert_eq!(Condition::parse("V3"), None);
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(Breakpoint { pc: Some(0x204), condition: None });
+        assert_eq!(debugger.resume(&mut chip8), Stop::Breakpoint(id));
+        assert_eq!(chip8.cpu.PC, 0x204);
+        assert_eq!(chip8.cpu.V[3], 1);
+
+        // Only when V3 reaches 3
+        debugger.delete(id);
+        let condition = Condition::parse("V3 == 3");
+        let id = debugger.add_breakpoint(Breakpoint { pc: Some(0x204), condition });
+        assert_eq!(debugger.resume(&mut chip8), Stop::Breakpoint(id));
+        assert_eq!(chip8.cpu.V[3], 3);
+
+        // The loop ends in a jump to itself
+        debugger.delete(id);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Waiting);
+        assert_eq!(chip8.cpu.PC, 0x208);
+    }
+
+    #[test]
+    fn conditions_without_address_break_anywhere() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(Breakpoint { pc: None, condition: Condition::parse("I == #300") });
+        assert_eq!(debugger.resume(&mut chip8), Stop::Breakpoint(id));
+        assert_eq!(chip8.cpu.PC, 0x20C);
+    }
+
+    #[test]
+    fn watchpoints_report_changes() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        let id = debugger.add_watchpoint(Target::Memory(0x303), &chip8);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Watchpoint { id, old: 0, new: 1 });
+        assert_eq!(chip8.cpu.PC, 0x20E);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Watchpoint { id, old: 1, new: 2 });
+    }
+
+    #[test]
+    fn step_over_runs_subroutine() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.step(&mut chip8, 1), Stop::Done);
+        assert_eq!(debugger.step_over(&mut chip8), Stop::Done);
+        assert_eq!(chip8.cpu.PC, 0x204);
+        assert_eq!(chip8.cpu.memory[0x303], 1);
+        assert_eq!(debugger.run_to(&mut chip8, 0x20A), Stop::Done);
+        assert_eq!(chip8.cpu.V[3], 2);
+    }
+
+    #[test]
+    fn runs_stop_at_the_limit() {
+        // 200: ADD V0, 1; 202: JP 200 never reaches 0x300
+        let mut chip8 = machine(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new();
+        debugger.run_limit = 1000;
+        assert_eq!(debugger.run_to(&mut chip8, 0x300), Stop::Limit(1000));
+        assert_eq!(chip8.cpu.V[0], (500 % 256) as u8);
+        assert_eq!(debugger.resume(&mut chip8), Stop::Limit(1000));
+
+        let mut out = Vec::new();
+        run_console(&mut chip8, "until 0x10200\nlimit 10\nuntil 0x300\n".as_bytes(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("invalid address 0x10200"));
+        assert!(text.contains("stopped after 10 instructions"));
+    }
+
+    #[test]
+    fn console_commands() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut out = Vec::new();
+        run_console(&mut chip8, "break 0x206 if V3 >= 2\nc\n\nregs\nbogus\nq\nstep\n".as_bytes(), &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("breakpoint 1\n0206: 1200  JP #200"));
+        assert!(text.contains("V3=03"));
+        assert!(text.contains("unknown command bogus"));
+        assert_eq!(chip8.cpu.V[3], 3);
+    }
+}
@@ -0,0 +1,255 @@
+// Disassembler: turns a ROM back into a listing of Cowgod style mnemonics.
+//
+// CHIP-8 programs mix code and data (sprites mostly) freely, so decoding
+// every byte pair as an instruction produces nonsense for the data. Instead
+// the code is found by following execution from the program start: through
+// jumps, calls and both outcomes of skips. Whatever is never reached is
+// listed as data bytes. Jump and call targets and the addresses I is loaded
+// with get labels.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::instruction::{decode, is_long, Instruction};
+use crate::rom::Rom;
+use crate::variant::Variant;
+
+pub struct Disassembly {
+    variant: Variant,
+    // Address of the first ROM byte.
+    start: u32,
+    bytes: Vec<u8>,
+    // Whether an instruction starts at each ROM byte.
+    code: Vec<bool>,
+    labels: BTreeMap<u32, String>,
+}
+
+impl Disassembly {
+    // Separate code from data by following execution from the program
+    // start.
+    pub fn new(rom: &Rom, variant: Variant) -> Disassembly {
+        let mut disassembly = Disassembly::empty(rom, variant);
+        let mut pending = vec![disassembly.start];
+        while let Some(addr) = pending.pop() {
+            if disassembly.is_code(addr) {
+                continue;
+            }
+            let (instruction, len) = match disassembly.decode_at(addr) {
+                Some(Some(decoded)) => decoded,
+                // Unknown opcodes and the end of the ROM end the path
+                _ => continue,
+            };
+            disassembly.code[(addr - disassembly.start) as usize] = true;
+            disassembly.add_label(&instruction);
+            let next = addr + len;
+            match instruction {
+                Instruction::Jp(target) => pending.push(target as u32),
+                Instruction::Call(target) => pending.extend_from_slice(&[next, target as u32]),
+                // The jump table itself is code, where it leads can't be
+                // known without running the program
+                Instruction::JpV0(target) => pending.push(target as u32),
+                Instruction::Ret | Instruction::Exit => {}
+                Instruction::Se(..) | Instruction::Sne(..) | Instruction::SeReg(..) | Instruction::SneReg(..) |
+                Instruction::Skp(_) | Instruction::Sknp(_) | Instruction::Skp2(_) | Instruction::Sknp2(_) => {
+                    pending.push(next);
+                    // Skips jump over a whole long instruction
+                    if let Some(Some((_, skipped))) = disassembly.decode_at(next) {
+                        pending.push(next + skipped);
+                    }
+                }
+                _ => pending.push(next),
+            }
+        }
+        disassembly
+    }
+
+    // Decode every byte pair in order, as if the whole ROM was code.
+    pub fn linear(rom: &Rom, variant: Variant) -> Disassembly {
+        let mut disassembly = Disassembly::empty(rom, variant);
+        let mut addr = disassembly.start;
+        while let Some(decoded) = disassembly.decode_at(addr) {
+            let len = match decoded {
+                Some((instruction, len)) => {
+                    disassembly.add_label(&instruction);
+                    len
+                }
+                None => 2,
+            };
+            disassembly.code[(addr - disassembly.start) as usize] = true;
+            addr += len;
+        }
+        disassembly
+    }
+
+    fn empty(rom: &Rom, variant: Variant) -> Disassembly {
+        Disassembly {
+            variant,
+            start: variant.program_start() as u32,
+            bytes: rom.bytes().to_vec(),
+            code: vec![false; rom.len()],
+            labels: BTreeMap::new(),
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.start + self.bytes.len() as u32
+    }
+
+    fn is_code(&self, addr: u32) -> bool {
+        addr >= self.start && addr < self.end() && self.code[(addr - self.start) as usize]
+    }
+
+    fn word(&self, addr: u32) -> Option<u16> {
+        if addr < self.start || addr + 2 > self.end() {
+            return None;
+        }
+        let offset = (addr - self.start) as usize;
+        Some(u16::from_be_bytes([self.bytes[offset], self.bytes[offset + 1]]))
+    }
+
+    // The instruction at addr and its length, None inside if the opcode is
+    // unknown, None outside if it doesn't fit in the ROM.
+    fn decode_at(&self, addr: u32) -> Option<Option<(Instruction, u32)>> {
+        let opcode = self.word(addr)?;
+        let (instruction, len) = if is_long(self.variant, opcode) {
+            (decode(self.variant, opcode, self.word(addr + 2)?), 4)
+        } else {
+            (decode(self.variant, opcode, 0), 2)
+        };
+        match instruction {
+            Instruction::Unknown(_) => Some(None),
+            _ => Some(Some((instruction, len))),
+        }
+    }
+
+    fn add_label(&mut self, instruction: &Instruction) {
+        let (addr, prefix) = match *instruction {
+            Instruction::Jp(addr) | Instruction::JpV0(addr) => (addr as u32, "loc"),
+            Instruction::Call(addr) => (addr as u32, "sub"),
+            Instruction::LdI(addr) => (addr as u32, "data"),
+            Instruction::LdILong(addr) => (addr, "data"),
+            _ => return,
+        };
+        // Addresses outside of the ROM, such as the fonts, stay numbers
+        if addr < self.start || addr >= self.end() {
+            return;
+        }
+        // Calls name a location even if it is also jumped to
+        let label = self.labels.entry(addr).or_insert_with(|| format!("{}_{:03X}", prefix, addr));
+        if prefix == "sub" {
+            *label = format!("sub_{:03X}", addr);
+        }
+    }
+
+    pub fn label(&self, addr: u32) -> Option<&str> {
+        self.labels.get(&addr).map(|label| label.as_str())
+    }
+
+    // The mnemonic, with addresses replaced by their labels.
+    fn mnemonic(&self, instruction: &Instruction) -> String {
+        let label = |addr: u32| self.label(addr).map(|label| label.to_string());
+        let named = match *instruction {
+            Instruction::Jp(addr) => label(addr as u32).map(|label| format!("JP {}", label)),
+            Instruction::Call(addr) => label(addr as u32).map(|label| format!("CALL {}", label)),
+            Instruction::JpV0(addr) => label(addr as u32).map(|label| format!("JP V0, {}", label)),
+            Instruction::LdI(addr) => label(addr as u32).map(|label| format!("LD I, {}", label)),
+            Instruction::LdILong(addr) => label(addr).map(|label| format!("LD I, LONG {}", label)),
+            _ => None,
+        };
+        named.unwrap_or_else(|| instruction.to_string())
+    }
+
+    // The listing: one line per instruction or data byte, with address, raw
+    // bytes and the mnemonic. Data bytes show their bits as they would look
+    // in a sprite.
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let mut addr = self.start;
+        while addr < self.end() {
+            if let Some(label) = self.label(addr) {
+                let _ = writeln!(out, "{}:", label);
+            }
+            let offset = (addr - self.start) as usize;
+            let decoded = match self.decode_at(addr) {
+                Some(Some((instruction, len))) if self.code[offset] => {
+                    // An instruction overlapping the next one is shown as data
+                    let overlaps = (1..len).any(|i| self.is_code(addr + i) || self.label(addr + i).is_some());
+                    if overlaps { None } else { Some((instruction, len)) }
+                }
+                _ => None,
+            };
+            match decoded {
+                Some((instruction, len)) => {
+                    let raw: Vec<String> = self.bytes[offset..offset + len as usize].iter().map(|byte| format!("{:02X}", byte)).collect();
+                    let _ = writeln!(out, "    {:04X}  {:<12}{}", addr, raw.join(" "), self.mnemonic(&instruction));
+                    addr += len;
+                }
+                None => {
+                    let byte = self.bytes[offset];
+                    let bits: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+                    let _ = writeln!(out, "    {:04X}  {:<12}DB #{:02X}  ; {}", addr, format!("{:02X}", byte), byte, bits);
+                    addr += 1;
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(bytes: &[u8]) -> Rom {
+        Rom::from_bytes(bytes.to_vec()).unwrap()
+    }
+
+    // 200: LD I, 20A; 202: CALL 208; 204: DRW V0, V0, 2; 206: JP 204
+    // 208: RET; 20A: sprite data that would decode as SYS and SE.
+    const PROGRAM: [u8; 14] = [0xA2, 0x0A, 0x22, 0x08, 0xD0, 0x02, 0x12, 0x04, 0x00, 0xEE, 0x0F, 0xF0, 0x3C, 0x3C];
+
+    #[test]
+    fn separates_code_and_data() {
+        let listing = Disassembly::new(&rom(&PROGRAM), Variant::Chip8).listing();
+        let expected = [
+            "    0200  A2 0A       LD I, data_20A",
+            "    0202  22 08       CALL sub_208",
+            "loc_204:",
+            "    0204  D0 02       DRW V0, V0, 2",
+            "    0206  12 04       JP loc_204",
+            "sub_208:",
+            "    0208  00 EE       RET",
+            "data_20A:",
+            "    020A  0F          DB #0F  ; ....####",
+            "    020B  F0          DB #F0  ; ####....",
+            "    020C  3C          DB #3C  ; ..####..",
+            "    020D  3C          DB #3C  ; ..####..",
+        ];
+        assert_eq!(listing.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn linear_decodes_everything() {
+        let listing = Disassembly::linear(&rom(&PROGRAM), Variant::Chip8).listing();
+        assert!(listing.contains("020C  3C 3C       SE VC, #3C"));
+    }
+
+    #[test]
+    fn follows_both_sides_of_skips() {
+        // 200: SE V0, 0; 202: JP 206; 204: JP 204; 206: EXIT (SUPER-CHIP)
+        let program = [0x30, 0x00, 0x12, 0x06, 0x12, 0x04, 0x00, 0xFD, 0xFF];
+        let disassembly = Disassembly::new(&rom(&program), Variant::SuperChip);
+        assert!(disassembly.is_code(0x204));
+        assert!(disassembly.is_code(0x206));
+        assert!(!disassembly.is_code(0x208));
+    }
+
+    #[test]
+    fn skips_over_long_instructions() {
+        // 200: SNE V0, 0; 202: LD I, LONG 1234; 206: EXIT
+        let program = [0x40, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xFD];
+        let listing = Disassembly::new(&rom(&program), Variant::XoChip).listing();
+        assert!(listing.contains("0202  F0 00 12 34 LD I, LONG #1234"));
+        assert!(listing.contains("0206  00 FD       EXIT"));
+    }
+}
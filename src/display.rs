@@ -0,0 +1,607 @@
+use crate::megachip::{self, MegaChip};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::variant::Variant;
+
+// Display dimensions in pixels for the original low resolution mode.
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+// SUPER-CHIP high resolution mode.
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+// MEGACHIP mode.
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
+
+// What happens to sprite pixels that fall off the edge of the screen. The
+// starting coordinate always wraps, so drawing at x=70 starts at x=6.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpriteWrap {
+    // Pixels past the right or bottom edge are not drawn.
+    #[default]
+    Clip,
+    // Pixels past an edge reappear on the opposite side.
+    Wrap,
+}
+
+// CHIP-8X colour zones are 8 pixels wide, each row of pixels can have its own
+// colours.
+pub const ZONE_WIDTH: usize = 8;
+// CHIP-8X foreground colour index of every zone at startup (red), and the
+// number of background colours 02A0 cycles through.
+pub const DEFAULT_FOREGROUND: u8 = 1;
+pub const BACKGROUND_COLORS: u8 = 4;
+
+// Bit planes selected when the display is created. CHIP-8 and SUPER-CHIP
+// only ever use the first one.
+pub const DEFAULT_PLANES: u8 = 0b01;
+// XO-CHIP has two planes, giving four colours.
+pub const ALL_PLANES: u8 = 0b11;
+
+// Display whose resolution can change at runtime. Pixels are stored row by
+// row, each one holding a bit per plane, so the value of a pixel is its
+// colour index. In MEGACHIP mode a pixel holds a palette entry instead, and
+// the blended ARGB colour is kept alongside it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    // Planes that drawing, clearing and scrolling apply to (XO-CHIP Fn01).
+    planes: u8,
+    pixels: Vec<u8>,
+    // MEGACHIP colours being drawn and the ones shown since the last frame
+    // was finished. Both are empty outside of MEGACHIP mode.
+    argb: Vec<u32>,
+    shown: Vec<u32>,
+    // CHIP-8X foreground colour of each 8x1 zone, empty on other variants,
+    // and the background colour.
+    zone_colors: Vec<u8>,
+    background: u8,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            planes: DEFAULT_PLANES,
+            pixels: vec![0; width * height],
+            argb: Vec::new(),
+            shown: Vec::new(),
+            zone_colors: Vec::new(),
+            background: 0,
+        }
+    }
+
+    // The display a machine of the variant starts with.
+    pub fn for_variant(variant: Variant) -> FrameBuffer {
+        let (width, height) = variant.display_size();
+        let mut frame_buffer = FrameBuffer::new(width, height);
+        if variant == Variant::Chip8X {
+            frame_buffer.zone_colors = vec![DEFAULT_FOREGROUND; width / ZONE_WIDTH * height];
+        }
+        frame_buffer
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Whether the display is in the SUPER-CHIP 128x64 mode.
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    pub fn is_mega(&self) -> bool {
+        !self.argb.is_empty()
+    }
+
+    // Switch MEGACHIP mode on or off, either way the screen is cleared.
+    pub fn set_mega(&mut self, enabled: bool) {
+        if enabled {
+            *self = FrameBuffer::new(MEGA_WIDTH, MEGA_HEIGHT);
+            // Every bit of a pixel is part of its palette entry
+            self.planes = 0xFF;
+            self.argb = vec![0; MEGA_WIDTH * MEGA_HEIGHT];
+            self.shown = self.argb.clone();
+        } else {
+            *self = FrameBuffer::default();
+        }
+    }
+
+    // The ARGB colour of a pixel in the last finished MEGACHIP frame.
+    pub fn shown_argb(&self, x: usize, y: usize) -> u32 {
+        self.shown[y * self.width + x]
+    }
+
+    // Finish a MEGACHIP frame, making what has been drawn visible.
+    pub fn present(&mut self) {
+        self.shown.copy_from_slice(&self.argb);
+    }
+
+    pub fn has_color_zones(&self) -> bool {
+        !self.zone_colors.is_empty()
+    }
+
+    // CHIP-8X foreground colour index of the zone the pixel is in.
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        self.zone_colors[y * (self.width / ZONE_WIDTH) + x / ZONE_WIDTH]
+    }
+
+    // CHIP-8X background colour index.
+    pub fn background(&self) -> u8 {
+        self.background
+    }
+
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUND_COLORS;
+    }
+
+    // Set the foreground colour of the zones covering the area, clipped to
+    // the screen. `x` and `width` are in pixels, but always cover whole
+    // zones.
+    pub fn set_zone_color(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        if !self.has_color_zones() {
+            return;
+        }
+        let zones = self.width / ZONE_WIDTH;
+        let first = x / ZONE_WIDTH;
+        let last = ((x + width.max(1) - 1) / ZONE_WIDTH).min(zones - 1);
+        for row in y..(y + height).min(self.height) {
+            for zone in first..=last {
+                self.zone_colors[row * zones + zone] = color;
+            }
+        }
+    }
+
+    // Whether the pixel is lit in any plane.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    // Colour index of the pixel, bit n is set when it is lit in plane n.
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    // Light or clear the pixel in the selected planes.
+    pub fn set(&mut self, x: usize, y: usize, lit: bool) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        if lit {
+            *pixel |= self.planes;
+        } else {
+            *pixel &= !self.planes;
+        }
+    }
+
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    // Clear the selected planes.
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !self.planes;
+        }
+        for color in self.argb.iter_mut() {
+            *color = 0;
+        }
+    }
+
+    // Change the resolution, which clears the screen. The plane selection
+    // is kept.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let planes = self.planes;
+        *self = FrameBuffer::new(width, height);
+        self.planes = planes;
+    }
+
+    // XOR a sprite onto the selected planes with its top left corner at
+    // (x, y). Each row of the sprite is `row_bytes` bytes wide, most
+    // significant bit leftmost, so ordinary sprites have 1 byte rows and
+    // SUPER-CHIP 16x16 sprites 2 byte rows. When more than one plane is
+    // selected the sprite data for each plane follows the previous one.
+    // Returns whether any lit pixel was turned off.
+    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8], row_bytes: usize, wrap: SpriteWrap) -> bool {
+        let plane_count = self.planes.count_ones() as usize;
+        if plane_count == 0 || sprite.is_empty() {
+            return false;
+        }
+        let plane_len = sprite.len() / plane_count;
+        let mut collision = false;
+        let mut data = sprite.chunks(plane_len);
+        for plane in 0..8 {
+            let plane_bit = 1 << plane;
+            if self.planes & plane_bit == 0 {
+                continue;
+            }
+            if let Some(plane_data) = data.next() {
+                collision |= self.draw_plane(x, y, plane_data, row_bytes, wrap, plane_bit);
+            }
+        }
+        collision
+    }
+
+    fn draw_plane(&mut self, x: u8, y: u8, sprite: &[u8], row_bytes: usize, wrap: SpriteWrap, plane_bit: u8) -> bool {
+        let x = x as usize % self.width;
+        let y = y as usize % self.height;
+        let mut collision = false;
+        for (row, row_data) in sprite.chunks(row_bytes).enumerate() {
+            for (byte_index, sprite_byte) in row_data.iter().enumerate() {
+                for bit in 0..8 {
+                    if sprite_byte & (0x80 >> bit) == 0 {
+                        continue;
+                    }
+                    let col = byte_index * 8 + bit;
+                    let (fb_x, fb_y) = match wrap {
+                        SpriteWrap::Clip => {
+                            if x + col >= self.width || y + row >= self.height {
+                                continue;
+                            }
+                            (x + col, y + row)
+                        }
+                        SpriteWrap::Wrap => ((x + col) % self.width, (y + row) % self.height),
+                    };
+
+                    // set collision register
+                    let index = fb_y * self.width + fb_x;
+                    if self.pixels[index] & plane_bit != 0 {
+                        collision = true;
+                    }
+                    self.pixels[index] ^= plane_bit;
+                }
+            }
+        }
+        collision
+    }
+
+    // Draw a MEGACHIP sprite of palette entries, `width` entries per row.
+    // Entry 0 is transparent and the others replace the pixel below. Pixels
+    // past the edges are clipped. Returns whether a pixel of the collision
+    // colour was drawn over.
+    pub fn draw_mega_sprite(&mut self, x: u8, y: u8, width: usize, sprite: &[u8], mega: &MegaChip) -> bool {
+        let (x, y) = (x as usize, y as usize);
+        let mut collision = false;
+        for (row, row_data) in sprite.chunks(width).enumerate() {
+            for (col, &entry) in row_data.iter().enumerate() {
+                if entry == 0 || x + col >= self.width || y + row >= self.height {
+                    continue;
+                }
+                let index = (y + row) * self.width + x + col;
+                if self.pixels[index] == mega.collision_color {
+                    collision = true;
+                }
+                self.pixels[index] = entry;
+                self.argb[index] = megachip::blend(mega.palette[entry as usize], self.argb[index], mega.blend);
+            }
+        }
+        collision
+    }
+
+    // Move the selected planes by (dx, dy), blank pixels come in from the
+    // edges.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let old = self.pixels.clone();
+        let old_argb = self.argb.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let src = if src_x >= 0 && src_y >= 0 && (src_x as usize) < self.width && (src_y as usize) < self.height {
+                    Some(src_y as usize * self.width + src_x as usize)
+                } else {
+                    None
+                };
+                let index = y * self.width + x;
+                let moved = src.map_or(0, |src| old[src] & self.planes);
+                self.pixels[index] = (old[index] & !self.planes) | moved;
+                if self.is_mega() {
+                    self.argb[index] = src.map_or(0, |src| old_argb[src]);
+                }
+            }
+        }
+    }
+
+    // Move the picture down by n rows, blank rows come in at the top.
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n.min(self.height) as isize);
+    }
+
+    // Move the picture up by n rows, blank rows come in at the bottom.
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n.min(self.height) as isize));
+    }
+
+    // Move the picture right by n columns, blank columns come in on the left.
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n.min(self.width) as isize, 0);
+    }
+
+    // Move the picture left by n columns, blank columns come in on the right.
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n.min(self.width) as isize), 0);
+    }
+
+    // Render the display as text, one line per row. Unlit pixels are '.',
+    // pixels lit in the first plane only '#', in the second plane only '+'
+    // and in both '*'. In MEGACHIP mode any palette entry other than 0 is '#'.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        let mask = if self.is_mega() { 0xFF } else { ALL_PLANES };
+        for row in self.pixels.chunks(self.width) {
+            for pixel in row {
+                text.push(match pixel & mask {
+                    0 => '.',
+                    _ if self.is_mega() => '#',
+                    1 => '#',
+                    2 => '+',
+                    _ => '*',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> FrameBuffer {
+        FrameBuffer::new(WIDTH, HEIGHT)
+    }
+}
+
+fn save_colors(out: &mut StateWriter, colors: &[u32]) {
+    out.u32(colors.len() as u32);
+    for &color in colors {
+        out.u32(color);
+    }
+}
+
+fn load_colors(input: &mut StateReader) -> Result<Vec<u32>, StateError> {
+    let len = input.u32()?;
+    let mut colors = Vec::new();
+    for _ in 0..len {
+        colors.push(input.u32()?);
+    }
+    Ok(colors)
+}
+
+impl Snapshot for FrameBuffer {
+    fn save(&self, out: &mut StateWriter) {
+        out.u16(self.width as u16);
+        out.u16(self.height as u16);
+        out.u8(self.planes);
+        out.bytes(&self.pixels);
+        save_colors(out, &self.argb);
+        save_colors(out, &self.shown);
+        out.bytes(&self.zone_colors);
+        out.u8(self.background);
+    }
+
+    fn load(input: &mut StateReader) -> Result<FrameBuffer, StateError> {
+        let width = input.u16()? as usize;
+        let height = input.u16()? as usize;
+        match (width, height) {
+            (WIDTH, HEIGHT) | (WIDTH, WIDTH) | (HIRES_WIDTH, HIRES_HEIGHT) | (MEGA_WIDTH, MEGA_HEIGHT) => {}
+            _ => return Err(StateError::Invalid("display size")),
+        }
+        let frame_buffer = FrameBuffer {
+            width,
+            height,
+            planes: input.u8()?,
+            pixels: input.bytes()?,
+            argb: load_colors(input)?,
+            shown: load_colors(input)?,
+            zone_colors: input.bytes()?,
+            background: input.u8()?,
+        };
+        let size = width * height;
+        let mega = frame_buffer.argb.len();
+        if frame_buffer.pixels.len() != size || (mega != 0 && mega != size) || frame_buffer.shown.len() != mega {
+            return Err(StateError::Invalid("display contents"));
+        }
+        let zones = frame_buffer.zone_colors.len();
+        if zones != 0 && zones != width / ZONE_WIDTH * height {
+            return Err(StateError::Invalid("colour zones"));
+        }
+        Ok(frame_buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A solid 8x4 block
+    const BLOCK: [u8; 4] = [0xFF; 4];
+
+    fn lit(frame_buffer: &FrameBuffer) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in 0..frame_buffer.height() {
+            for x in 0..frame_buffer.width() {
+                if frame_buffer.get(x, y) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    fn block_at(xs: &[usize], ys: &[usize]) -> Vec<(usize, usize)> {
+        let mut pixels = Vec::new();
+        for y in ys {
+            for x in xs {
+                pixels.push((*x, *y));
+            }
+        }
+        pixels.sort_by_key(|&(x, y)| (y, x));
+        pixels
+    }
+
+    fn draw(x: u8, y: u8, wrap: SpriteWrap) -> Vec<(usize, usize)> {
+        let mut frame_buffer = FrameBuffer::default();
+        assert!(!frame_buffer.draw_sprite(x, y, &BLOCK, 1, wrap));
+        lit(&frame_buffer)
+    }
+
+    fn range(start: usize, len: usize, size: usize) -> Vec<usize> {
+        (start..start + len).map(|v| v % size).collect()
+    }
+
+    #[test]
+    fn draws_inside_the_screen_the_same_either_way() {
+        let expected = block_at(&range(10, 8, WIDTH), &range(5, 4, HEIGHT));
+        assert_eq!(draw(10, 5, SpriteWrap::Clip), expected);
+        assert_eq!(draw(10, 5, SpriteWrap::Wrap), expected);
+    }
+
+    #[test]
+    fn top_left_corner() {
+        let expected = block_at(&range(0, 8, WIDTH), &range(0, 4, HEIGHT));
+        assert_eq!(draw(0, 0, SpriteWrap::Clip), expected);
+        assert_eq!(draw(0, 0, SpriteWrap::Wrap), expected);
+    }
+
+    #[test]
+    fn exactly_fitting_bottom_right_corner() {
+        let expected = block_at(&range(56, 8, WIDTH), &range(28, 4, HEIGHT));
+        assert_eq!(draw(56, 28, SpriteWrap::Clip), expected);
+        assert_eq!(draw(56, 28, SpriteWrap::Wrap), expected);
+    }
+
+    #[test]
+    fn right_edge() {
+        assert_eq!(draw(60, 10, SpriteWrap::Clip), block_at(&range(60, 4, WIDTH), &range(10, 4, HEIGHT)));
+        assert_eq!(draw(60, 10, SpriteWrap::Wrap), block_at(&range(60, 8, WIDTH), &range(10, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn last_column() {
+        assert_eq!(draw(63, 10, SpriteWrap::Clip), block_at(&[63], &range(10, 4, HEIGHT)));
+        assert_eq!(draw(63, 10, SpriteWrap::Wrap), block_at(&range(63, 8, WIDTH), &range(10, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn bottom_edge() {
+        assert_eq!(draw(20, 30, SpriteWrap::Clip), block_at(&range(20, 8, WIDTH), &[30, 31]));
+        assert_eq!(draw(20, 30, SpriteWrap::Wrap), block_at(&range(20, 8, WIDTH), &range(30, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn last_row() {
+        assert_eq!(draw(20, 31, SpriteWrap::Clip), block_at(&range(20, 8, WIDTH), &[31]));
+        assert_eq!(draw(20, 31, SpriteWrap::Wrap), block_at(&range(20, 8, WIDTH), &range(31, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn bottom_right_corner() {
+        assert_eq!(draw(62, 30, SpriteWrap::Clip), block_at(&[62, 63], &[30, 31]));
+        assert_eq!(draw(62, 30, SpriteWrap::Wrap), block_at(&range(62, 8, WIDTH), &range(30, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn top_right_corner() {
+        assert_eq!(draw(61, 0, SpriteWrap::Clip), block_at(&[61, 62, 63], &range(0, 4, HEIGHT)));
+        assert_eq!(draw(61, 0, SpriteWrap::Wrap), block_at(&range(61, 8, WIDTH), &range(0, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn bottom_left_corner() {
+        assert_eq!(draw(0, 29, SpriteWrap::Clip), block_at(&range(0, 8, WIDTH), &[29, 30, 31]));
+        assert_eq!(draw(0, 29, SpriteWrap::Wrap), block_at(&range(0, 8, WIDTH), &range(29, 4, HEIGHT)));
+    }
+
+    #[test]
+    fn start_coordinates_wrap() {
+        // (70, 40) is (6, 8) on screen for both behaviours
+        let expected = block_at(&range(6, 8, WIDTH), &range(8, 4, HEIGHT));
+        assert_eq!(draw(70, 40, SpriteWrap::Clip), expected);
+        assert_eq!(draw(70, 40, SpriteWrap::Wrap), expected);
+        assert_eq!(draw(255, 255, SpriteWrap::Clip), block_at(&[63], &[31]));
+    }
+
+    #[test]
+    fn collision_is_reported_on_wrapped_pixels() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set(0, 0, true);
+        assert!(frame_buffer.draw_sprite(63, 31, &[0xC0, 0xC0], 1, SpriteWrap::Wrap));
+        assert!(!frame_buffer.get(0, 0));
+
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set(0, 0, true);
+        assert!(!frame_buffer.draw_sprite(63, 31, &[0xC0, 0xC0], 1, SpriteWrap::Clip));
+        assert!(frame_buffer.get(0, 0));
+    }
+
+    #[test]
+    fn large_sprite_in_hires() {
+        let mut frame_buffer = FrameBuffer::new(HIRES_WIDTH, HIRES_HEIGHT);
+        let sprite = [0xFF; 32];
+        assert!(!frame_buffer.draw_sprite(120, 60, &sprite, 2, SpriteWrap::Clip));
+        assert_eq!(lit(&frame_buffer), block_at(&range(120, 8, HIRES_WIDTH), &range(60, 4, HIRES_HEIGHT)));
+
+        let mut frame_buffer = FrameBuffer::new(HIRES_WIDTH, HIRES_HEIGHT);
+        assert!(!frame_buffer.draw_sprite(120, 60, &sprite, 2, SpriteWrap::Wrap));
+        assert_eq!(lit(&frame_buffer), block_at(&range(120, 16, HIRES_WIDTH), &range(60, 16, HIRES_HEIGHT)));
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set(0, 0, true);
+        frame_buffer.set(63, 31, true);
+        frame_buffer.scroll_down(1);
+        assert_eq!(lit(&frame_buffer), vec![(0, 1)]);
+        frame_buffer.scroll_right(4);
+        assert_eq!(lit(&frame_buffer), vec![(4, 1)]);
+        frame_buffer.scroll_left(4);
+        frame_buffer.scroll_left(4);
+        assert_eq!(lit(&frame_buffer), vec![]);
+    }
+
+    #[test]
+    fn resize_clears() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.set(3, 3, true);
+        frame_buffer.resize(HIRES_WIDTH, HIRES_HEIGHT);
+        assert!(frame_buffer.is_hires());
+        assert_eq!(lit(&frame_buffer), vec![]);
+    }
+
+    #[test]
+    fn planes_draw_consecutive_sprite_data() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.select_planes(ALL_PLANES);
+        assert!(!frame_buffer.draw_sprite(0, 0, &[0xC0, 0x80], 1, SpriteWrap::Clip));
+        assert_eq!(frame_buffer.pixel(0, 0), 3);
+        assert_eq!(frame_buffer.pixel(1, 0), 1);
+
+        frame_buffer.select_planes(0b10);
+        assert!(frame_buffer.draw_sprite(0, 0, &[0x80], 1, SpriteWrap::Clip));
+        assert_eq!(frame_buffer.pixel(0, 0), 1);
+        frame_buffer.select_planes(0);
+        assert!(!frame_buffer.draw_sprite(0, 0, &[0x80], 1, SpriteWrap::Clip));
+        assert_eq!(frame_buffer.pixel(0, 0), 1);
+    }
+
+    #[test]
+    fn clear_and_scroll_only_touch_selected_planes() {
+        let mut frame_buffer = FrameBuffer::default();
+        frame_buffer.select_planes(ALL_PLANES);
+        frame_buffer.set(0, 0, true);
+        frame_buffer.select_planes(0b10);
+        frame_buffer.scroll_down(2);
+        assert_eq!((frame_buffer.pixel(0, 0), frame_buffer.pixel(0, 2)), (1, 2));
+        frame_buffer.scroll_up(2);
+        assert_eq!(frame_buffer.pixel(0, 0), 3);
+        frame_buffer.clear();
+        assert_eq!(frame_buffer.pixel(0, 0), 1);
+    }
+}
@@ -0,0 +1,55 @@
+use std::error::Error;
+use std::fmt;
+
+// What happened when an instruction executed successfully.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    // The instruction ran and PC moved on.
+    Executed,
+    // Fx0A found no key pressed, PC stays on the instruction so it runs again.
+    WaitingForKey,
+    // DRW with the display wait quirk, nothing else runs until the next frame.
+    WaitForFrame,
+    // SUPER-CHIP 00FD, the program has finished. PC stays on the instruction.
+    Exited,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKind {
+    // CALL with all 16 stack slots in use.
+    StackOverflow,
+    // RET with an empty stack.
+    StackUnderflow,
+    // PC points outside of memory, so no instruction can be fetched.
+    PcOutOfBounds,
+    // An instruction accessed memory at or past the end of RAM through I.
+    MemoryOutOfBounds { addr: usize },
+}
+
+// A program error that stops the machine. The CPU state is left as it was
+// before the faulting instruction, with PC still pointing at it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CpuFault {
+    pub kind: FaultKind,
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::PcOutOfBounds => write!(f, "PC out of bounds"),
+            FaultKind::MemoryOutOfBounds { addr } => write!(f, "memory access out of bounds ({:04X})", addr),
+        }
+    }
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at PC {:04X} (opcode {:04X})", self.kind, self.pc, self.opcode)
+    }
+}
+
+impl Error for CpuFault {}
@@ -0,0 +1,527 @@
+use std::sync::Arc;
+
+use log::{error, info, warn};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::chip8::Chip8;
+use crate::display::{FrameBuffer, WIDTH};
+use crate::fault::CpuFault;
+use crate::input::InputMap;
+use crate::megachip::Sample;
+use crate::movie::{Movie, Replay};
+use crate::rewind::{self, Rewind};
+use crate::savestate;
+use crate::scheduler::FrameScheduler;
+
+const PIXEL_SCALING: u32 = 10;
+const SAMPLE_RATE: i32 = 44100;
+
+// Frontend configuration that doesn't affect the emulated machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    // Pitch of the buzzer in Hz.
+    pub tone: f32,
+    // Buzzer amplitude between 0.0 and 1.0.
+    pub volume: f32,
+    // Don't open an audio device at all.
+    pub mute: bool,
+    // Save state slot N is the file `<state_base>.stateN`, usually the ROM
+    // path. Without it the slot hotkeys do nothing.
+    pub state_base: Option<String>,
+    // How far back the rewind key can go, zero disables rewinding, and how
+    // much memory the rewind buffer may use in bytes.
+    pub rewind_seconds: u32,
+    pub rewind_memory: usize,
+    // Record a movie to this file, written when the window closes.
+    pub record: Option<String>,
+    // Play a movie back, after which the keyboard takes over.
+    pub replay: Option<Movie>,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            tone: 440.0,
+            volume: 0.25,
+            mute: false,
+            state_base: None,
+            rewind_seconds: rewind::DEFAULT_SECONDS,
+            rewind_memory: rewind::DEFAULT_MEMORY_CAP,
+            record: None,
+            replay: None,
+        }
+    }
+}
+
+// Colours for each pixel value. Only the first two are used unless an
+// XO-CHIP program draws to the second plane.
+const PALETTE: [Color; 4] = [Color::RGB(0, 0, 0), Color::RGB(255, 255, 255), Color::RGB(170, 170, 170), Color::RGB(85, 85, 85)];
+
+// CHIP-8X (VP-590) foreground colours set by BxyN: black, red, blue, violet,
+// green, yellow, aqua and white.
+const ZONE_COLORS: [Color; 8] = [Color::RGB(0, 0, 0), Color::RGB(255, 0, 0), Color::RGB(0, 0, 255), Color::RGB(255, 0, 255),
+                                 Color::RGB(0, 255, 0), Color::RGB(255, 255, 0), Color::RGB(0, 255, 255), Color::RGB(255, 255, 255)];
+// CHIP-8X background colours in the order 02A0 cycles through: blue, black,
+// green and red.
+const BACKGROUND_COLORS: [Color; 4] = [Color::RGB(0, 0, 128), Color::RGB(0, 0, 0), Color::RGB(0, 128, 0), Color::RGB(128, 0, 0)];
+
+// Square wave generator used as the CHIP-8 buzzer. When an XO-CHIP program
+// has loaded an audio pattern the pattern's bits are played instead, and a
+// MEGACHIP sample takes precedence over both.
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    // XO-CHIP pattern, its playback rate in bits per output sample and the
+    // position in bits.
+    pattern: Option<[u8; 16]>,
+    pattern_inc: f32,
+    pattern_pos: f32,
+    // MEGACHIP sample, its rate in sample bytes per output sample and the
+    // position in bytes.
+    sample: Option<Arc<Sample>>,
+    sample_inc: f32,
+    sample_pos: f32,
+}
+
+impl SquareWave {
+    // The next output level of the sample between -1.0 and 1.0, silence
+    // once a sample that doesn't loop has finished.
+    fn next_sample_level(&mut self, sample: &Sample) -> f32 {
+        let len = sample.data.len() as f32;
+        if sample.looping && len > 0.0 {
+            self.sample_pos %= len;
+        }
+        match sample.data.get(self.sample_pos as usize) {
+            Some(&value) => {
+                self.sample_pos += self.sample_inc;
+                (value as f32 - 128.0) / 128.0
+            }
+            None => 0.0,
+        }
+    }
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for sample in out.iter_mut() {
+            if let Some(playing) = self.sample.clone() {
+                *sample = self.next_sample_level(&playing) * self.volume;
+                continue;
+            }
+            let high = match self.pattern {
+                Some(pattern) => {
+                    let bit = self.pattern_pos as usize;
+                    self.pattern_pos = (self.pattern_pos + self.pattern_inc) % 128.0;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => {
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                    self.phase <= 0.5
+                }
+            };
+            *sample = if high { self.volume } else { -self.volume };
+        }
+    }
+}
+
+// Plays the square wave while the sound timer is active.
+struct Beeper {
+    device: AudioDevice<SquareWave>,
+    playing: bool,
+    // Pattern and rate in samples per second the callback currently uses.
+    pattern: Option<([u8; 16], f32)>,
+    // The MEGACHIP sample the callback is playing.
+    sample: Option<Arc<Sample>>,
+}
+
+impl Beeper {
+    fn open(audio_subsystem: &sdl2::AudioSubsystem, settings: &Settings) -> Result<Beeper, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            SquareWave {
+                phase_inc: settings.tone / spec.freq as f32,
+                phase: 0.0,
+                volume: settings.volume.clamp(0.0, 1.0),
+                pattern: None,
+                pattern_inc: 0.0,
+                pattern_pos: 0.0,
+                sample: None,
+                sample_inc: 0.0,
+                sample_pos: 0.0,
+            }
+        })?;
+        Ok(Beeper { device, playing: false, pattern: None, sample: None })
+    }
+
+    // Start playing a MEGACHIP sample from the beginning, or stop it.
+    fn set_sample(&mut self, sample: Option<&Arc<Sample>>) {
+        let unchanged = match (sample, &self.sample) {
+            (Some(new), Some(old)) => Arc::ptr_eq(new, old),
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            let freq = self.device.spec().freq as f32;
+            let mut wave = self.device.lock();
+            wave.sample = sample.cloned();
+            wave.sample_inc = sample.map_or(0.0, |sample| sample.rate as f32 / freq);
+            wave.sample_pos = 0.0;
+            self.sample = sample.cloned();
+        }
+    }
+
+    // Switch to playing an XO-CHIP audio pattern at the given rate.
+    fn set_pattern(&mut self, pattern: Option<([u8; 16], f32)>) {
+        if pattern != self.pattern {
+            let freq = self.device.spec().freq as f32;
+            let mut wave = self.device.lock();
+            wave.pattern = pattern.map(|(bits, _)| bits);
+            wave.pattern_inc = pattern.map_or(0.0, |(_, rate)| rate / freq);
+            self.pattern = pattern;
+        }
+    }
+
+    fn set_playing(&mut self, playing: bool) {
+        if playing != self.playing {
+            if playing {
+                self.device.resume();
+            } else {
+                self.device.pause();
+            }
+            self.playing = playing;
+        }
+    }
+}
+
+// A missing audio device shouldn't stop the game from running, so failures
+// here only disable sound.
+fn open_beeper(sdl_context: &sdl2::Sdl, settings: &Settings) -> Option<Beeper> {
+    if settings.mute {
+        return None;
+    }
+    match sdl_context.audio().and_then(|audio| Beeper::open(&audio, settings)) {
+        Ok(beeper) => Some(beeper),
+        Err(error) => {
+            warn!("Sound disabled, could not open audio device: {}", error);
+            None
+        }
+    }
+}
+
+// Maps a keyboard key onto the CHIP-8 keypad:
+//
+//  Q W E R      1 2 3 C
+//  A S D F  ->  4 5 6 D
+//  Z X C V      7 8 9 E
+//  U I O P      A 0 B F
+fn keypad_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Q => Some(0x1),
+        Keycode::W => Some(0x2),
+        Keycode::E => Some(0x3),
+        Keycode::R => Some(0xC),
+        Keycode::A => Some(0x4),
+        Keycode::S => Some(0x5),
+        Keycode::D => Some(0x6),
+        Keycode::F => Some(0xD),
+        Keycode::Z => Some(0x7),
+        Keycode::X => Some(0x8),
+        Keycode::C => Some(0x9),
+        Keycode::V => Some(0xE),
+        Keycode::U => Some(0xA),
+        Keycode::I => Some(0x0),
+        Keycode::O => Some(0xB),
+        Keycode::P => Some(0xF),
+        _ => None,
+    }
+}
+
+// Maps the numeric keypad onto the CHIP-8X second keypad, in the same layout
+// as the first:
+//
+//  7 8 9 /      1 2 3 C
+//  4 5 6 *  ->  4 5 6 D
+//  1 2 3 -      7 8 9 E
+//  0 . ⏎ +      A 0 B F
+fn keypad2_key(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::Kp7 => Some(0x1),
+        Keycode::Kp8 => Some(0x2),
+        Keycode::Kp9 => Some(0x3),
+        Keycode::KpDivide => Some(0xC),
+        Keycode::Kp4 => Some(0x4),
+        Keycode::Kp5 => Some(0x5),
+        Keycode::Kp6 => Some(0x6),
+        Keycode::KpMultiply => Some(0xD),
+        Keycode::Kp1 => Some(0x7),
+        Keycode::Kp2 => Some(0x8),
+        Keycode::Kp3 => Some(0x9),
+        Keycode::KpMinus => Some(0xE),
+        Keycode::Kp0 => Some(0xA),
+        Keycode::KpPeriod => Some(0x0),
+        Keycode::KpEnter => Some(0xB),
+        Keycode::KpPlus => Some(0xF),
+        _ => None,
+    }
+}
+
+fn input_event_map(input_map: &mut InputMap, event: Event) {
+    let (keycode, pressed) = match event {
+        Event::KeyDown { keycode: Some(keycode), .. } => (keycode, true),
+        Event::KeyUp { keycode: Some(keycode), .. } => (keycode, false),
+        _ => return,
+    };
+    if let Some(key) = keypad_key(keycode) {
+        input_map.set_key(key, pressed);
+    }
+    if let Some(key) = keypad2_key(keycode) {
+        input_map.set_key2(key, pressed);
+    }
+}
+
+// Save state slot selected by a function key, F1 to F9.
+fn state_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+// Save the machine to a slot, or load it back. Returns whether a state was
+// loaded.
+fn use_state_slot(chip8: &mut Chip8, base: &str, slot: u8, save: bool) -> bool {
+    let path = format!("{}.state{}", base, slot);
+    if save {
+        match savestate::save_state_file(chip8, &path) {
+            Ok(()) => info!("Saved state to {}", path),
+            Err(error) => warn!("Could not save state to {}: {}", path, error),
+        }
+        false
+    } else {
+        match savestate::load_state_file(chip8, &path) {
+            Ok(()) => {
+                info!("Loaded state from {}", path);
+                true
+            }
+            Err(error) => {
+                warn!("Could not load state from {}: {}", path, error);
+                false
+            }
+        }
+    }
+}
+
+// Screen pixels per emulated pixel. The window is as wide as the 64 pixel
+// wide display at PIXEL_SCALING, so high resolution pixels are drawn at half
+// the size.
+fn pixel_scaling(frame_buffer: &FrameBuffer) -> u32 {
+    (PIXEL_SCALING * WIDTH as u32 / frame_buffer.width() as u32).max(1)
+}
+
+// Colour of a MEGACHIP pixel in the last finished frame, faded by the screen
+// alpha.
+fn mega_color(argb: u32, alpha: u8) -> Color {
+    let fade = |shift: u32| (((argb >> shift) & 0xFF) * alpha as u32 / 0xFF) as u8;
+    Color::RGB(fade(16), fade(8), fade(0))
+}
+
+fn draw_grid(frame_buffer: &FrameBuffer, screen_alpha: u8, canvas: &mut Canvas<Window>) {
+    let pixel_scaling = pixel_scaling(frame_buffer);
+    // Leave a one pixel gap between cells when they are big enough
+    let size = if pixel_scaling > 5 { pixel_scaling - 1 } else { pixel_scaling };
+    for y in 0..frame_buffer.height() {
+        for x in 0..frame_buffer.width() {
+            let color = if frame_buffer.is_mega() {
+                match frame_buffer.shown_argb(x, y) & 0xFF_FFFF {
+                    0 => None,
+                    argb => Some(mega_color(argb, screen_alpha)),
+                }
+            } else if frame_buffer.has_color_zones() {
+                match frame_buffer.pixel(x, y) {
+                    0 => None,
+                    _ => Some(ZONE_COLORS[frame_buffer.foreground(x, y) as usize % ZONE_COLORS.len()]),
+                }
+            } else {
+                match frame_buffer.pixel(x, y) as usize {
+                    0 => None,
+                    pixel => Some(PALETTE[pixel % PALETTE.len()]),
+                }
+            };
+            if let Some(color) = color {
+                canvas.set_draw_color(color);
+                let _ = canvas.fill_rect(Rect::new(x as i32 * pixel_scaling as i32, y as i32 * pixel_scaling as i32, size, size));
+            }
+        }
+    }
+    canvas.present();
+}
+
+// Open a window and run the machine until the window is closed, Escape is
+// pressed or the program exits. If the program faults the machine halts with
+// the last frame left on screen, and the fault is returned once the window is
+// closed. Holding Backspace plays the recorded frames backwards, which also
+// gets a halted machine going again.
+pub fn run(chip8: &mut Chip8, settings: &Settings) -> Result<(), String> {
+    // setup multimedia loop
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let mut beeper = open_beeper(&sdl_context, settings);
+
+    let scaling = pixel_scaling(&chip8.frame_buffer);
+    let size = (chip8.frame_buffer.width() as u32 * scaling, chip8.frame_buffer.height() as u32 * scaling);
+    let window = video_subsystem.window("rchip8", size.0, size.1)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let mut event_pump = sdl_context.event_pump()?;
+    let mut scheduler = FrameScheduler::new();
+    let mut halted: Option<CpuFault> = None;
+    let mut rewind = Rewind::new(settings.rewind_seconds, settings.rewind_memory);
+    // Backspace is held down
+    let mut rewinding = false;
+    let mut recording = settings.record.as_ref().map(|_| Movie::record(chip8));
+    let mut replay = match settings.replay.clone() {
+        Some(movie) => Some(Replay::start(movie, chip8).map_err(|error| format!("Problem replaying movie: {}", error))?),
+        None => None,
+    };
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    break 'running;
+                },
+                // F1-F9 load a slot, with shift they save to it
+                Event::KeyDown { keycode: Some(keycode), keymod, repeat: false, .. } if state_slot(keycode).is_some() => {
+                    if let (Some(base), Some(slot)) = (settings.state_base.as_ref(), state_slot(keycode)) {
+                        let save = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                        if !save && (recording.is_some() || replay.is_some()) {
+                            warn!("Save states can't be loaded while a movie is recording or playing");
+                        } else if use_state_slot(chip8, base, slot, save) {
+                            // The recorded frames lead somewhere else now
+                            rewind.clear();
+                            if halted.take().is_some() {
+                                let _ = canvas.window_mut().set_title("rchip8");
+                            }
+                        }
+                    }
+                },
+                Event::KeyDown { keycode: Some(Keycode::Backspace), repeat: false, .. } => {
+                    if recording.is_some() || replay.is_some() {
+                        warn!("Rewinding isn't possible while a movie is recording or playing");
+                    } else {
+                        rewinding = settings.rewind_seconds > 0;
+                    }
+                },
+                Event::KeyUp { keycode: Some(Keycode::Backspace), .. } => rewinding = false,
+                _ => {
+                    input_event_map(&mut chip8.input, event);
+                }
+            }
+        }
+        if chip8.has_exited() {
+            break 'running;
+        }
+        // The machine runs however many frames are due, but we only render
+        // the latest one.
+        for _ in 0..scheduler.frames_due() {
+            if rewinding {
+                // Going back also undoes a fault
+                match rewind.step_back(chip8) {
+                    Ok(true) => {
+                        if halted.take().is_some() {
+                            let _ = canvas.window_mut().set_title("rchip8");
+                        }
+                    }
+                    Ok(false) => break,
+                    Err(fault) => error!("CPU fault while rewinding: {}", fault),
+                }
+                continue;
+            }
+            if halted.is_some() {
+                break;
+            }
+            if let Some(result) = replay.as_mut().map(|replay| replay.next_frame(chip8)) {
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        info!("Replay finished, the keyboard has control");
+                        replay = None;
+                    }
+                    Err(error) => {
+                        error!("{}", error);
+                        let _ = canvas.window_mut().set_title(&format!("rchip8 - {}", error));
+                        replay = None;
+                    }
+                }
+            }
+            if let Some(movie) = recording.as_mut() {
+                movie.record_frame(chip8);
+            }
+            if settings.rewind_seconds > 0 {
+                rewind.record(chip8);
+            }
+            if let Err(fault) = chip8.run_frame() {
+                error!("CPU fault: {}", fault);
+                let _ = canvas.window_mut().set_title(&format!("rchip8 - halted: {}", fault));
+                halted = Some(fault);
+            }
+        }
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.set_pattern(chip8.cpu.audio_pattern.map(|pattern| (pattern, chip8.pattern_rate())));
+            beeper.set_sample(chip8.cpu.megachip.sample.as_ref());
+            beeper.set_playing(halted.is_none() && !rewinding && (chip8.sound_active() || chip8.cpu.megachip.sample.is_some()));
+        }
+
+        // Modes with a different aspect ratio (MEGACHIP) resize the window
+        let scaling = pixel_scaling(&chip8.frame_buffer);
+        let size = (chip8.frame_buffer.width() as u32 * scaling, chip8.frame_buffer.height() as u32 * scaling);
+        if canvas.window().size() != size {
+            let _ = canvas.window_mut().set_size(size.0, size.1);
+        }
+
+        // Draw background:
+        if chip8.frame_buffer.has_color_zones() {
+            canvas.set_draw_color(BACKGROUND_COLORS[chip8.frame_buffer.background() as usize % BACKGROUND_COLORS.len()]);
+        } else {
+            canvas.set_draw_color(PALETTE[0]);
+        }
+        canvas.clear();
+        // Draw frame buffer
+        draw_grid(&chip8.frame_buffer, chip8.cpu.megachip.screen_alpha, &mut canvas);
+
+        ::std::thread::sleep(scheduler.until_next_frame());
+    }
+    if let (Some(mut movie), Some(path)) = (recording, settings.record.as_ref()) {
+        movie.finish(chip8);
+        movie.save_file(path).map_err(|error| format!("Problem writing movie {}: {}", path, error))?;
+        info!("Recorded {} frames to {}", movie.len(), path);
+    }
+    match halted {
+        Some(fault) => Err(format!("CPU fault: {}", fault)),
+        None => Ok(()),
+    }
+}
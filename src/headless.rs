@@ -0,0 +1,112 @@
+use std::io;
+use std::io::prelude::*;
+
+use crate::chip8::Chip8;
+use crate::fault::CpuFault;
+
+// How long to run a machine for when there is no display to close.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RunLimit {
+    // Number of instructions to execute.
+    Cycles(u64),
+    // Number of 60 Hz frames to run, timers are updated once per frame.
+    Frames(u64),
+}
+
+// Run the machine until the limit is reached, the program exits or it faults.
+pub fn run_headless(chip8: &mut Chip8, limit: RunLimit) -> Result<(), CpuFault> {
+    match limit {
+        RunLimit::Cycles(cycles) => {
+            // Run whole frames so the timers keep in step with the clock
+            // speed, then whatever is left of the last partial frame.
+            let mut remaining = cycles;
+            loop {
+                let frame_cycles = chip8.next_frame_cycles() as u64;
+                if frame_cycles > remaining {
+                    break;
+                }
+                remaining -= chip8.run_frame()? as u64;
+                if remaining == 0 || chip8.has_exited() {
+                    break;
+                }
+            }
+            for _ in 0..remaining {
+                if chip8.has_exited() {
+                    break;
+                }
+                chip8.step()?;
+            }
+        }
+        RunLimit::Frames(frames) => {
+            for _ in 0..frames {
+                if chip8.has_exited() {
+                    break;
+                }
+                chip8.run_frame()?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Write the registers, stack and frame buffer of the machine in a plain text
+// format, suitable for diffing between runs.
+pub fn write_state<W: Write>(chip8: &Chip8, out: &mut W) -> io::Result<()> {
+    let cpu = &chip8.cpu;
+    writeln!(out, "PC: {:04X} I: {:04X} SP: {:02X} DT: {:02X} ST: {:02X}", cpu.PC, cpu.I, cpu.SP, cpu.DT, cpu.ST)?;
+    for (reg, value) in cpu.V.iter().enumerate() {
+        if reg > 0 {
+            write!(out, " ")?;
+        }
+        write!(out, "V{:X}: {:02X}", reg, value)?;
+    }
+    writeln!(out)?;
+    write!(out, "S:")?;
+    for addr in cpu.S.iter() {
+        write!(out, " {:04X}", addr)?;
+    }
+    writeln!(out)?;
+    write!(out, "{}", chip8.frame_buffer.to_text())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // 200: LD V1, 30; 202: LD DT, V1; 204: ADD V0, 1; 206: JP 204
+    const PROGRAM: [u8; 8] = [0x61, 0x1E, 0xF1, 0x15, 0x70, 0x01, 0x12, 0x04];
+
+    fn machine() -> Chip8 {
+        Chip8::from_program(&PROGRAM, Variant::Chip8)
+    }
+
+    #[test]
+    fn runs_frames_and_dumps_state() {
+        let mut chip8 = machine();
+        run_headless(&mut chip8, RunLimit::Frames(3)).unwrap();
+        // 700 IPS runs 11, 12 and 12 instructions, the delay timer ticks
+        // once per frame
+        assert_eq!(chip8.cpu.DT, 27);
+        assert_eq!(chip8.cpu.V[0], 17);
+        let mut out = Vec::new();
+        write_state(&chip8, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "PC: 0206 I: 0000 SP: 00 DT: 1B ST: 00");
+        assert!(lines[1].starts_with("V0: 11 V1: 1E V2: 00"));
+        assert!(lines[2].starts_with("S: 0000"));
+        assert_eq!(lines.len(), 3 + 32);
+        assert!(lines[3..].iter().all(|row| *row == ".".repeat(64)));
+    }
+
+    #[test]
+    fn runs_exact_number_of_cycles() {
+        let mut chip8 = machine();
+        run_headless(&mut chip8, RunLimit::Cycles(25)).unwrap();
+        // Two whole frames of 11 and 12 instructions, then two more
+        assert_eq!(chip8.cpu.V[0], 12);
+        assert_eq!(chip8.cpu.PC, 0x0206);
+        assert_eq!(chip8.cpu.DT, 28);
+    }
+}
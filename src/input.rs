@@ -0,0 +1,79 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// State of the 16 key hexadecimal keypad:
+//
+//  1 2 3 C
+//  4 5 6 D
+//  7 8 9 E
+//  A 0 B F
+//
+// CHIP-8X machines have a second keypad with the same layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputMap {
+    keys: [bool; 16],
+    keys2: [bool; 16],
+}
+
+impl InputMap {
+    pub fn new() -> InputMap {
+        InputMap { keys: [false; 16], keys2: [false; 16] }
+    }
+
+    // Returns whether the key with the given hex value is held down. Values
+    // outside of 0x0-0xF are never pressed.
+    pub fn is_pressed(&self, key: u8) -> bool {
+        match self.keys.get(key as usize) {
+            Some(pressed) => *pressed,
+            None => false,
+        }
+    }
+
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.keys.get_mut(key as usize) {
+            *state = pressed;
+        }
+    }
+
+    // Same as `is_pressed` for the second keypad.
+    pub fn is_pressed2(&self, key: u8) -> bool {
+        match self.keys2.get(key as usize) {
+            Some(pressed) => *pressed,
+            None => false,
+        }
+    }
+
+    pub fn set_key2(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.keys2.get_mut(key as usize) {
+            *state = pressed;
+        }
+    }
+
+    // Both keypads as a bit per key, the first keypad in the low 16 bits.
+    pub fn to_bits(&self) -> u32 {
+        self.keys.iter().chain(self.keys2.iter()).enumerate().fold(0, |bits, (key, &pressed)| bits | (pressed as u32) << key)
+    }
+
+    pub fn from_bits(bits: u32) -> InputMap {
+        let mut map = InputMap::new();
+        for (key, pressed) in map.keys.iter_mut().chain(map.keys2.iter_mut()).enumerate() {
+            *pressed = bits & (1 << key) != 0;
+        }
+        map
+    }
+}
+
+impl Snapshot for InputMap {
+    fn save(&self, out: &mut StateWriter) {
+        for pressed in self.keys.iter().chain(self.keys2.iter()) {
+            out.bool(*pressed);
+        }
+    }
+
+    fn load(input: &mut StateReader) -> Result<InputMap, StateError> {
+        let mut map = InputMap::new();
+        for pressed in map.keys.iter_mut().chain(map.keys2.iter_mut()) {
+            *pressed = input.bool()?;
+        }
+        Ok(map)
+    }
+}
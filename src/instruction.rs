@@ -0,0 +1,476 @@
+use std::fmt;
+
+use crate::variant::Variant;
+
+// A decoded CHIP-8 instruction. Which extension opcodes decode to their own
+// instructions depends on the variant. Register operands are register numbers
+// (0x0-0xF), addresses are 12 bit and bytes are the low byte of the opcode.
+// Names follow Cowgod's technical reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    // 0nnn - Jump to a machine code routine, ignored by modern interpreters
+    Sys(u16),
+    // 00E0 - Clear the display
+    // 0230 - Same on the CHIP-8 hires variant
+    Cls,
+    // 00EE - Return from a subroutine
+    Ret,
+    // 02A0 - Cycle the background colour (CHIP-8X)
+    CycleBackground,
+    // 0010 - Leave MEGACHIP mode (MEGACHIP)
+    MegaOff,
+    // 0011 - Enter MEGACHIP mode with the 256x192 colour display (MEGACHIP)
+    MegaOn,
+    // 02nn - Load nn ARGB palette colours from I into entries 1 to nn (MEGACHIP)
+    LdPalette(u8),
+    // 03nn - Set the sprite width, 0 means 256 (MEGACHIP)
+    SpriteWidth(u8),
+    // 04nn - Set the sprite height, 0 means 256 (MEGACHIP)
+    SpriteHeight(u8),
+    // 05nn - Set the screen alpha (MEGACHIP)
+    ScreenAlpha(u8),
+    // 060n - Play the sample at I, once if n is 1, looped if 0 (MEGACHIP)
+    PlaySample(u8),
+    // 0700 - Stop the sample (MEGACHIP)
+    StopSample,
+    // 080n - Select the sprite blend mode (MEGACHIP)
+    Blend(u8),
+    // 09nn - Set the palette entry that counts as a collision (MEGACHIP)
+    CollisionColor(u8),
+    // 00Cn - Scroll the display down n pixels (SUPER-CHIP)
+    ScrollDown(u8),
+    // 00Dn - Scroll the display up n pixels (XO-CHIP)
+    // 00Bn - Same on MEGACHIP
+    ScrollUp(u8),
+    // 00FB - Scroll the display right 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    // 00FC - Scroll the display left 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    // 00FD - Exit the interpreter (SUPER-CHIP)
+    Exit,
+    // 00FE - Switch to the 64x32 low resolution mode (SUPER-CHIP)
+    Low,
+    // 00FF - Switch to the 128x64 high resolution mode (SUPER-CHIP)
+    High,
+    // 1nnn - Jump to address
+    Jp(u16),
+    // 2nnn - Call subroutine at address
+    Call(u16),
+    // 3xkk - Skip next instruction if Vx == kk
+    Se(u8, u8),
+    // 4xkk - Skip next instruction if Vx != kk
+    Sne(u8, u8),
+    // 5xy0 - Skip next instruction if Vx == Vy
+    SeReg(u8, u8),
+    // 5xy1 - Vx = Vx + Vy, adding each nibble separately modulo 8 (CHIP-8X)
+    AddNibbles(u8, u8),
+    // 5xy2 - Store Vx through Vy in memory starting at I (XO-CHIP)
+    LdStoreRange(u8, u8),
+    // 5xy3 - Read Vx through Vy from memory starting at I (XO-CHIP)
+    LdLoadRange(u8, u8),
+    // 6xkk - Vx = kk
+    Ld(u8, u8),
+    // 7xkk - Vx = Vx + kk
+    Add(u8, u8),
+    // 8xy0 - Vx = Vy
+    LdReg(u8, u8),
+    // 8xy1 - Vx = Vx OR Vy
+    Or(u8, u8),
+    // 8xy2 - Vx = Vx AND Vy
+    And(u8, u8),
+    // 8xy3 - Vx = Vx XOR Vy
+    Xor(u8, u8),
+    // 8xy4 - Vx = Vx + Vy, VF = carry
+    AddReg(u8, u8),
+    // 8xy5 - Vx = Vx - Vy, VF = NOT borrow
+    Sub(u8, u8),
+    // 8xy6 - Vx = Vx SHR 1, VF = shifted out bit
+    Shr(u8, u8),
+    // 8xy7 - Vx = Vy - Vx, VF = NOT borrow
+    Subn(u8, u8),
+    // 8xyE - Vx = Vx SHL 1, VF = shifted out bit
+    Shl(u8, u8),
+    // 9xy0 - Skip next instruction if Vx != Vy
+    SneReg(u8, u8),
+    // Annn - I = nnn
+    LdI(u16),
+    // Bnnn - Jump to nnn + V0
+    JpV0(u16),
+    // BxyN - Set the foreground colour of an area to Vy, see `execute`
+    // (CHIP-8X)
+    ColorZone(u8, u8, u8),
+    // Cxkk - Vx = random byte AND kk
+    Rnd(u8, u8),
+    // Dxyn - Draw n byte sprite at (Vx, Vy), VF = collision. With n = 0
+    // SUPER-CHIP draws a 16x16 sprite.
+    Drw(u8, u8, u8),
+    // F000 nnnn - I = nnnn, a 16 bit address in the following word (XO-CHIP)
+    // 01nn nnnn - I = nnnnnn, a 24 bit address (MEGACHIP)
+    LdILong(u32),
+    // Fn01 - Select the bit planes drawn to, as a mask (XO-CHIP)
+    Plane(u8),
+    // F002 - Load the 16 byte audio pattern at I (XO-CHIP)
+    LdAudio,
+    // Ex9E - Skip next instruction if key Vx is pressed
+    Skp(u8),
+    // ExA1 - Skip next instruction if key Vx is not pressed
+    Sknp(u8),
+    // ExF2 - Skip next instruction if key Vx on the second keypad is pressed
+    // (CHIP-8X)
+    Skp2(u8),
+    // ExF5 - Skip next instruction if key Vx on the second keypad is not
+    // pressed (CHIP-8X)
+    Sknp2(u8),
+    // Fx07 - Vx = DT
+    LdVxDt(u8),
+    // Fx0A - Wait for a key press, store the key in Vx
+    LdKey(u8),
+    // Fx15 - DT = Vx
+    LdDtVx(u8),
+    // Fx18 - ST = Vx
+    LdStVx(u8),
+    // Fx1E - I = I + Vx
+    AddI(u8),
+    // Fx29 - I = location of the hex sprite for digit Vx
+    LdFont(u8),
+    // Fx30 - I = location of the large hex sprite for digit Vx (SUPER-CHIP)
+    LdHiFont(u8),
+    // Fx33 - Store BCD representation of Vx at I, I+1 and I+2
+    LdBcd(u8),
+    // Fx3A - Set the audio pattern playback pitch to Vx (XO-CHIP)
+    LdPitch(u8),
+    // Fx55 - Store V0 through Vx in memory starting at I
+    LdStore(u8),
+    // Fx65 - Read V0 through Vx from memory starting at I
+    LdLoad(u8),
+    // Fx75 - Store V0 through Vx in the RPL user flags (SUPER-CHIP)
+    LdRplStore(u8),
+    // Fx85 - Read V0 through Vx from the RPL user flags (SUPER-CHIP)
+    LdRplLoad(u8),
+    // FxF8 - Write Vx to the output port, the sound board pitch (CHIP-8X)
+    Out(u8),
+    // FxFB - Read the input port into Vx (CHIP-8X)
+    In(u8),
+    // Anything else
+    Unknown(u16),
+}
+
+// Whether the instruction is followed by a second word holding part of its
+// operand (XO-CHIP F000, MEGACHIP 01nn).
+pub fn is_long(variant: Variant, opcode: u16) -> bool {
+    match variant {
+        Variant::XoChip => opcode == 0xF000,
+        Variant::MegaChip => opcode & 0xFF00 == 0x0100,
+        _ => false,
+    }
+}
+
+// Split an opcode into its four nibbles, most significant first.
+fn nibbles(opcode: u16) -> (u8, u8, u8, u8) {
+    (((opcode & 0xF000) >> 12) as u8,
+     ((opcode & 0x0F00) >> 8) as u8,
+     ((opcode & 0x00F0) >> 4) as u8,
+     (opcode & 0x000F) as u8)
+}
+
+// Decode the opcode for the variant. `operand` is the word following the
+// opcode and is only used when `is_long` is true. Extension opcodes the
+// variant doesn't have decode as on the original CHIP-8.
+pub fn decode(variant: Variant, opcode: u16, operand: u16) -> Instruction {
+    let (op, x, y, n) = nibbles(opcode);
+    let nnn = opcode & 0x0FFF;
+    let kk = (opcode & 0x00FF) as u8;
+    let schip = variant.has_schip();
+    let xo = variant.has_xo();
+    let mega = variant == Variant::MegaChip;
+    let hires = variant == Variant::Chip8Hires;
+    let x8 = variant == Variant::Chip8X;
+
+    match (op, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+        (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+        (0x0, 0x2, 0x3, 0x0) if hires => Instruction::Cls,
+        (0x0, 0x2, 0xA, 0x0) if x8 => Instruction::CycleBackground,
+        (0x0, 0x0, 0x1, 0x0) if mega => Instruction::MegaOff,
+        (0x0, 0x0, 0x1, 0x1) if mega => Instruction::MegaOn,
+        (0x0, 0x1, _, _) if mega => Instruction::LdILong(((kk as u32) << 16) | operand as u32),
+        (0x0, 0x2, _, _) if mega => Instruction::LdPalette(kk),
+        (0x0, 0x3, _, _) if mega => Instruction::SpriteWidth(kk),
+        (0x0, 0x4, _, _) if mega => Instruction::SpriteHeight(kk),
+        (0x0, 0x5, _, _) if mega => Instruction::ScreenAlpha(kk),
+        (0x0, 0x6, 0x0, _) if mega => Instruction::PlaySample(n),
+        (0x0, 0x7, 0x0, 0x0) if mega => Instruction::StopSample,
+        (0x0, 0x8, 0x0, _) if mega => Instruction::Blend(n),
+        (0x0, 0x9, _, _) if mega => Instruction::CollisionColor(kk),
+        (0x0, 0x0, 0xB, _) if mega => Instruction::ScrollUp(n),
+        (0x0, 0x0, 0xC, _) if schip => Instruction::ScrollDown(n),
+        (0x0, 0x0, 0xD, _) if xo => Instruction::ScrollUp(n),
+        (0x0, 0x0, 0xF, 0xB) if schip => Instruction::ScrollRight,
+        (0x0, 0x0, 0xF, 0xC) if schip => Instruction::ScrollLeft,
+        (0x0, 0x0, 0xF, 0xD) if schip => Instruction::Exit,
+        (0x0, 0x0, 0xF, 0xE) if schip => Instruction::Low,
+        (0x0, 0x0, 0xF, 0xF) if schip => Instruction::High,
+        (0x0, _, _, _) => Instruction::Sys(nnn),
+        (0x1, _, _, _) => Instruction::Jp(nnn),
+        (0x2, _, _, _) => Instruction::Call(nnn),
+        (0x3, _, _, _) => Instruction::Se(x, kk),
+        (0x4, _, _, _) => Instruction::Sne(x, kk),
+        (0x5, _, _, 0x0) => Instruction::SeReg(x, y),
+        (0x5, _, _, 0x1) if x8 => Instruction::AddNibbles(x, y),
+        (0x5, _, _, 0x2) if xo => Instruction::LdStoreRange(x, y),
+        (0x5, _, _, 0x3) if xo => Instruction::LdLoadRange(x, y),
+        (0x6, _, _, _) => Instruction::Ld(x, kk),
+        (0x7, _, _, _) => Instruction::Add(x, kk),
+        (0x8, _, _, 0x0) => Instruction::LdReg(x, y),
+        (0x8, _, _, 0x1) => Instruction::Or(x, y),
+        (0x8, _, _, 0x2) => Instruction::And(x, y),
+        (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+        (0x8, _, _, 0x4) => Instruction::AddReg(x, y),
+        (0x8, _, _, 0x5) => Instruction::Sub(x, y),
+        (0x8, _, _, 0x6) => Instruction::Shr(x, y),
+        (0x8, _, _, 0x7) => Instruction::Subn(x, y),
+        (0x8, _, _, 0xE) => Instruction::Shl(x, y),
+        (0x9, _, _, 0x0) => Instruction::SneReg(x, y),
+        (0xA, _, _, _) => Instruction::LdI(nnn),
+        (0xB, _, _, _) if x8 => Instruction::ColorZone(x, y, n),
+        (0xB, _, _, _) => Instruction::JpV0(nnn),
+        (0xC, _, _, _) => Instruction::Rnd(x, kk),
+        (0xD, _, _, _) => Instruction::Drw(x, y, n),
+        (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+        (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+        (0xE, _, 0xF, 0x2) if x8 => Instruction::Skp2(x),
+        (0xE, _, 0xF, 0x5) if x8 => Instruction::Sknp2(x),
+        (0xF, 0x0, 0x0, 0x0) if xo => Instruction::LdILong(operand as u32),
+        (0xF, _, 0x0, 0x1) if xo => Instruction::Plane(x),
+        (0xF, 0x0, 0x0, 0x2) if xo => Instruction::LdAudio,
+        (0xF, _, 0x0, 0x7) => Instruction::LdVxDt(x),
+        (0xF, _, 0x0, 0xA) => Instruction::LdKey(x),
+        (0xF, _, 0x1, 0x5) => Instruction::LdDtVx(x),
+        (0xF, _, 0x1, 0x8) => Instruction::LdStVx(x),
+        (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+        (0xF, _, 0x2, 0x9) => Instruction::LdFont(x),
+        (0xF, _, 0x3, 0x0) if schip => Instruction::LdHiFont(x),
+        (0xF, _, 0x3, 0x3) => Instruction::LdBcd(x),
+        (0xF, _, 0x3, 0xA) if xo => Instruction::LdPitch(x),
+        (0xF, _, 0x5, 0x5) => Instruction::LdStore(x),
+        (0xF, _, 0x6, 0x5) => Instruction::LdLoad(x),
+        (0xF, _, 0x7, 0x5) if schip => Instruction::LdRplStore(x),
+        (0xF, _, 0x8, 0x5) if schip => Instruction::LdRplLoad(x),
+        (0xF, _, 0xF, 0x8) if x8 => Instruction::Out(x),
+        (0xF, _, 0xF, 0xB) if x8 => Instruction::In(x),
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+// Names of the groups `Instruction::class` puts instructions in.
+pub const CLASSES: [&str; 9] = ["flow", "skip", "alu", "memory", "display", "timer", "sound", "io", "unknown"];
+
+impl Instruction {
+    // The group of related instructions this one belongs to, for filtering
+    // traces.
+    pub fn class(&self) -> &'static str {
+        use Instruction::*;
+        match self {
+            Sys(_) | Ret | Jp(_) | Call(_) | JpV0(_) | Exit => "flow",
+            Se(..) | Sne(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) | Skp2(_) | Sknp2(_) => "skip",
+            Ld(..) | Add(..) | LdReg(..) | Or(..) | And(..) | Xor(..) | AddReg(..) | Sub(..) | Shr(..) | Subn(..) | Shl(..) | AddNibbles(..) |
+            Rnd(..) => "alu",
+            LdI(_) | LdILong(_) | AddI(_) | LdFont(_) | LdHiFont(_) | LdBcd(_) | LdStore(_) | LdLoad(_) | LdStoreRange(..) | LdLoadRange(..) |
+            LdRplStore(_) | LdRplLoad(_) => "memory",
+            Cls | ScrollDown(_) | ScrollUp(_) | ScrollRight | ScrollLeft | Low | High | Drw(..) | Plane(_) | CycleBackground | ColorZone(..) |
+            MegaOff | MegaOn | LdPalette(_) | SpriteWidth(_) | SpriteHeight(_) | ScreenAlpha(_) | Blend(_) | CollisionColor(_) => "display",
+            LdVxDt(_) | LdDtVx(_) => "timer",
+            LdStVx(_) | LdAudio | LdPitch(_) | PlaySample(_) | StopSample => "sound",
+            LdKey(_) | Out(_) | In(_) => "io",
+            Unknown(_) => "unknown",
+        }
+    }
+}
+
+// Formats instructions as Cowgod style mnemonics, e.g. `DRW V1, V2, 5`.
+// Addresses and bytes are written in hex with a `#` prefix.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(addr) => write!(f, "SYS #{:03X}", addr),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::CycleBackground => write!(f, "BGCOL"),
+            Instruction::MegaOff => write!(f, "MEGAOFF"),
+            Instruction::MegaOn => write!(f, "MEGAON"),
+            Instruction::LdPalette(n) => write!(f, "LDPAL {}", n),
+            Instruction::SpriteWidth(n) => write!(f, "SPRW {}", n),
+            Instruction::SpriteHeight(n) => write!(f, "SPRH {}", n),
+            Instruction::ScreenAlpha(n) => write!(f, "ALPHA #{:02X}", n),
+            Instruction::PlaySample(n) => write!(f, "DIGISND {}", n),
+            Instruction::StopSample => write!(f, "STOPSND"),
+            Instruction::Blend(n) => write!(f, "BMODE {}", n),
+            Instruction::CollisionColor(n) => write!(f, "CCOL #{:02X}", n),
+            Instruction::ScrollDown(n) => write!(f, "SCD {}", n),
+            Instruction::ScrollUp(n) => write!(f, "SCU {}", n),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(addr) => write!(f, "JP #{:03X}", addr),
+            Instruction::Call(addr) => write!(f, "CALL #{:03X}", addr),
+            Instruction::Se(x, kk) => write!(f, "SE V{:X}, #{:02X}", x, kk),
+            Instruction::Sne(x, kk) => write!(f, "SNE V{:X}, #{:02X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::AddNibbles(x, y) => write!(f, "ADDN V{:X}, V{:X}", x, y),
+            Instruction::LdStoreRange(x, y) => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            Instruction::LdLoadRange(x, y) => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            Instruction::Ld(x, kk) => write!(f, "LD V{:X}, #{:02X}", x, kk),
+            Instruction::Add(x, kk) => write!(f, "ADD V{:X}, #{:02X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(addr) => write!(f, "LD I, #{:03X}", addr),
+            Instruction::JpV0(addr) => write!(f, "JP V0, #{:03X}", addr),
+            Instruction::ColorZone(x, y, n) => write!(f, "COL V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, #{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::LdILong(addr) => write!(f, "LD I, LONG #{:04X}", addr),
+            Instruction::Plane(n) => write!(f, "PLANE {}", n),
+            Instruction::LdAudio => write!(f, "LD AUDIO, [I]"),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::Skp2(x) => write!(f, "SKP2 V{:X}", x),
+            Instruction::Sknp2(x) => write!(f, "SKNP2 V{:X}", x),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdKey(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFont(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdHiFont(x) => write!(f, "LD HF, V{:X}", x),
+            Instruction::LdBcd(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdPitch(x) => write!(f, "LD PITCH, V{:X}", x),
+            Instruction::LdStore(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdLoad(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::LdRplStore(x) => write!(f, "LD R, V{:X}", x),
+            Instruction::LdRplLoad(x) => write!(f, "LD V{:X}, R", x),
+            Instruction::Out(x) => write!(f, "OUT V{:X}", x),
+            Instruction::In(x) => write!(f, "IN V{:X}", x),
+            Instruction::Unknown(opcode) => write!(f, "DW #{:04X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Instruction::*;
+    use Variant::*;
+
+    #[test]
+    fn decodes_for_each_variant() {
+        let table = [
+            (Chip8, 0x00E0, Cls),
+            (Chip8, 0x00EE, Ret),
+            (Chip8, 0x0123, Sys(0x123)),
+            (Chip8, 0x1ABC, Jp(0xABC)),
+            (Chip8, 0x2ABC, Call(0xABC)),
+            (Chip8, 0x3A12, Se(0xA, 0x12)),
+            (Chip8, 0x4A12, Sne(0xA, 0x12)),
+            (Chip8, 0x5AB0, SeReg(0xA, 0xB)),
+            (Chip8, 0x6A12, Ld(0xA, 0x12)),
+            (Chip8, 0x7A12, Add(0xA, 0x12)),
+            (Chip8, 0x8AB0, LdReg(0xA, 0xB)),
+            (Chip8, 0x8AB1, Or(0xA, 0xB)),
+            (Chip8, 0x8AB2, And(0xA, 0xB)),
+            (Chip8, 0x8AB3, Xor(0xA, 0xB)),
+            (Chip8, 0x8AB4, AddReg(0xA, 0xB)),
+            (Chip8, 0x8AB5, Sub(0xA, 0xB)),
+            (Chip8, 0x8AB6, Shr(0xA, 0xB)),
+            (Chip8, 0x8AB7, Subn(0xA, 0xB)),
+            (Chip8, 0x8ABE, Shl(0xA, 0xB)),
+            (Chip8, 0x9AB0, SneReg(0xA, 0xB)),
+            (Chip8, 0xA123, LdI(0x123)),
+            (Chip8, 0xB123, JpV0(0x123)),
+            (Chip8, 0xCA12, Rnd(0xA, 0x12)),
+            (Chip8, 0xDAB5, Drw(0xA, 0xB, 5)),
+            (Chip8, 0xEA9E, Skp(0xA)),
+            (Chip8, 0xEAA1, Sknp(0xA)),
+            (Chip8, 0xFA07, LdVxDt(0xA)),
+            (Chip8, 0xFA0A, LdKey(0xA)),
+            (Chip8, 0xFA15, LdDtVx(0xA)),
+            (Chip8, 0xFA18, LdStVx(0xA)),
+            (Chip8, 0xFA1E, AddI(0xA)),
+            (Chip8, 0xFA29, LdFont(0xA)),
+            (Chip8, 0xFA33, LdBcd(0xA)),
+            (Chip8, 0xFA55, LdStore(0xA)),
+            (Chip8, 0xFA65, LdLoad(0xA)),
+            // Holes in the instruction set
+            (Chip8, 0x5AB1, Unknown(0x5AB1)),
+            (Chip8, 0x8AB8, Unknown(0x8AB8)),
+            (Chip8, 0x9AB1, Unknown(0x9AB1)),
+            (Chip8, 0xEA00, Unknown(0xEA00)),
+            (Chip8, 0xFA99, Unknown(0xFA99)),
+            // Extensions only decode on the variants that have them
+            (Chip8, 0x00FF, Sys(0x0FF)),
+            (SuperChip, 0x00FF, High),
+            (SuperChip, 0x00FE, Low),
+            (SuperChip, 0x00FD, Exit),
+            (SuperChip, 0x00FB, ScrollRight),
+            (SuperChip, 0x00FC, ScrollLeft),
+            (SuperChip, 0x00C4, ScrollDown(4)),
+            (Chip8, 0x00C4, Sys(0x0C4)),
+            (SuperChip, 0x00D4, Sys(0x0D4)),
+            (XoChip, 0x00D4, ScrollUp(4)),
+            (SuperChip, 0xFA30, LdHiFont(0xA)),
+            (Chip8, 0xFA30, Unknown(0xFA30)),
+            (SuperChip, 0xFA75, LdRplStore(0xA)),
+            (SuperChip, 0xFA85, LdRplLoad(0xA)),
+            (Chip8, 0xFA75, Unknown(0xFA75)),
+            (XoChip, 0x5AB2, LdStoreRange(0xA, 0xB)),
+            (XoChip, 0x5AB3, LdLoadRange(0xA, 0xB)),
+            (SuperChip, 0x5AB2, Unknown(0x5AB2)),
+            (XoChip, 0xF201, Plane(2)),
+            (XoChip, 0xF002, LdAudio),
+            (XoChip, 0xFA3A, LdPitch(0xA)),
+            (SuperChip, 0xFA3A, Unknown(0xFA3A)),
+            (Chip8Hires, 0x0230, Cls),
+            (Chip8, 0x0230, Sys(0x230)),
+            (Chip8X, 0x02A0, CycleBackground),
+            (Chip8X, 0x5AB1, AddNibbles(0xA, 0xB)),
+            (Chip8X, 0xBAB3, ColorZone(0xA, 0xB, 3)),
+            (Chip8X, 0xEAF2, Skp2(0xA)),
+            (Chip8X, 0xEAF5, Sknp2(0xA)),
+            (Chip8X, 0xFAF8, Out(0xA)),
+            (Chip8X, 0xFAFB, In(0xA)),
+            (Chip8, 0xEAF2, Unknown(0xEAF2)),
+            (MegaChip, 0x0011, MegaOn),
+            (MegaChip, 0x0010, MegaOff),
+            (MegaChip, 0x0203, LdPalette(3)),
+            (MegaChip, 0x0310, SpriteWidth(0x10)),
+            (MegaChip, 0x0420, SpriteHeight(0x20)),
+            (MegaChip, 0x0601, PlaySample(1)),
+            (MegaChip, 0x0700, StopSample),
+            (MegaChip, 0x0802, Blend(2)),
+            (MegaChip, 0x00B3, ScrollUp(3)),
+            (MegaChip, 0x00FF, High),
+        ];
+        for &(variant, opcode, expected) in table.iter() {
+            assert_eq!(decode(variant, opcode, 0), expected, "{:04X} on {:?}", opcode, variant);
+        }
+    }
+
+    #[test]
+    fn decodes_long_operands() {
+        assert!(is_long(XoChip, 0xF000));
+        assert!(!is_long(SuperChip, 0xF000));
+        assert!(is_long(MegaChip, 0x0112));
+        assert_eq!(decode(XoChip, 0xF000, 0xBEEF), LdILong(0xBEEF));
+        assert_eq!(decode(MegaChip, 0x0112, 0x3456), LdILong(0x123456));
+        assert_eq!(decode(SuperChip, 0xF000, 0xBEEF), Unknown(0xF000));
+    }
+}
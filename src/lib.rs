@@ -0,0 +1,44 @@
+//! CHIP-8 interpreter core.
+//!
+//! The emulator is split into the machine state (`CPU`), the display
+//! (`FrameBuffer`), the keypad (`InputMap`) and ROM loading. `Chip8` ties
+//! these together into a single machine that frontends can drive one
+//! instruction at a time. The SDL frontend is only built with the `sdl`
+//! feature, `headless` runs a machine without any display. `savestate`
+//! writes the whole machine to a file and restores it.
+
+pub mod asm;
+pub mod chip8;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod fault;
+#[cfg(feature = "sdl")]
+pub mod frontend;
+pub mod headless;
+pub mod input;
+pub mod instruction;
+pub mod megachip;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod rom;
+pub mod savestate;
+pub mod scheduler;
+pub mod trace;
+pub mod tracediff;
+pub mod variant;
+
+pub use chip8::Chip8;
+pub use cpu::{execute, init_cpu, run, CPU};
+pub use display::FrameBuffer;
+pub use fault::{CpuFault, FaultKind, StepOutcome};
+pub use input::InputMap;
+pub use instruction::{decode, Instruction};
+pub use quirks::Quirks;
+pub use rom::{load_rom, Rom, RomError};
+pub use savestate::{load_state, save_state, StateError};
+pub use variant::Variant;
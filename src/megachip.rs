@@ -0,0 +1,187 @@
+// MEGACHIP machine state that isn't part of the display or the CPU
+// registers: the palette, sprite settings and the playing sample.
+
+use std::sync::Arc;
+
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// Palette entry used for the built in font when drawing in MEGACHIP mode.
+pub const FONT_COLOR: u8 = 0xFF;
+
+// How sprite pixels are combined with the pixels already on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    // Replace, using the alpha channel of the palette colour.
+    Normal,
+    // Mix 25% of the sprite colour into the screen.
+    Quarter,
+    // Mix 50% of the sprite colour into the screen.
+    Half,
+    // Add the colour channels, saturating.
+    Add,
+    // Multiply the colour channels.
+    Multiply,
+}
+
+impl BlendMode {
+    // The mode selected by 080n, unknown modes act as normal.
+    pub fn from_code(code: u8) -> BlendMode {
+        match code {
+            1 => BlendMode::Quarter,
+            2 => BlendMode::Half,
+            3 => BlendMode::Add,
+            4 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    // The 080n code that selects this mode.
+    pub fn code(&self) -> u8 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Quarter => 1,
+            BlendMode::Half => 2,
+            BlendMode::Add => 3,
+            BlendMode::Multiply => 4,
+        }
+    }
+}
+
+// An 8 bit unsigned PCM sample started by 060n.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    // Samples per second.
+    pub rate: u16,
+    pub data: Vec<u8>,
+    // Start again from the beginning when the end is reached.
+    pub looping: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MegaChip {
+    // Whether 0011 switched the machine into MEGACHIP mode.
+    pub enabled: bool,
+    // ARGB colours, entry 0 is always transparent.
+    pub palette: [u32; 256],
+    // Size of the sprites Dxyn draws in MEGACHIP mode.
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    // Opacity of the whole screen, used by demos to fade in and out.
+    pub screen_alpha: u8,
+    pub blend: BlendMode,
+    // Drawing over a pixel of this palette entry sets VF.
+    pub collision_color: u8,
+    // The sample being played, shared with the audio thread of a frontend.
+    pub sample: Option<Arc<Sample>>,
+}
+
+impl MegaChip {
+    pub fn new() -> MegaChip {
+        let mut palette = [0; 256];
+        palette[FONT_COLOR as usize] = 0xFFFF_FFFF;
+        MegaChip {
+            enabled: false,
+            palette,
+            sprite_width: 0,
+            sprite_height: 0,
+            screen_alpha: 0xFF,
+            blend: BlendMode::Normal,
+            collision_color: 0,
+            sample: None,
+        }
+    }
+}
+
+impl Default for MegaChip {
+    fn default() -> MegaChip {
+        MegaChip::new()
+    }
+}
+
+impl Snapshot for MegaChip {
+    fn save(&self, out: &mut StateWriter) {
+        out.bool(self.enabled);
+        for &color in self.palette.iter() {
+            out.u32(color);
+        }
+        out.u16(self.sprite_width as u16);
+        out.u16(self.sprite_height as u16);
+        out.u8(self.screen_alpha);
+        out.u8(self.blend.code());
+        out.u8(self.collision_color);
+        match &self.sample {
+            Some(sample) => {
+                out.bool(true);
+                out.u16(sample.rate);
+                out.bool(sample.looping);
+                out.bytes(&sample.data);
+            }
+            None => out.bool(false),
+        }
+    }
+
+    fn load(input: &mut StateReader) -> Result<MegaChip, StateError> {
+        let enabled = input.bool()?;
+        let mut palette = [0; 256];
+        for color in palette.iter_mut() {
+            *color = input.u32()?;
+        }
+        Ok(MegaChip {
+            enabled,
+            palette,
+            sprite_width: input.u16()? as usize,
+            sprite_height: input.u16()? as usize,
+            screen_alpha: input.u8()?,
+            blend: BlendMode::from_code(input.u8()?),
+            collision_color: input.u8()?,
+            sample: if input.bool()? {
+                Some(Arc::new(Sample { rate: input.u16()?, looping: input.bool()?, data: input.bytes()? }))
+            } else {
+                None
+            },
+        })
+    }
+}
+
+fn channel(color: u32, shift: u32) -> u32 {
+    (color >> shift) & 0xFF
+}
+
+// Combine a sprite colour with the screen colour below it. The result is
+// always opaque.
+pub fn blend(src: u32, dst: u32, mode: BlendMode) -> u32 {
+    let alpha = match mode {
+        BlendMode::Normal => channel(src, 24),
+        BlendMode::Quarter => 0x40,
+        BlendMode::Half => 0x80,
+        BlendMode::Add | BlendMode::Multiply => 0xFF,
+    };
+    let mut result = 0xFF00_0000;
+    for &shift in &[16, 8, 0] {
+        let s = channel(src, shift);
+        let d = channel(dst, shift);
+        let value = match mode {
+            BlendMode::Add => (s + d).min(0xFF),
+            BlendMode::Multiply => s * d / 0xFF,
+            _ => (s * alpha + d * (0xFF - alpha)) / 0xFF,
+        };
+        result |= value << shift;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_modes() {
+        let src = 0x80FF_0000;
+        let dst = 0xFF00_00FF;
+        assert_eq!(blend(0xFF12_3456, dst, BlendMode::Normal), 0xFF12_3456);
+        assert_eq!(blend(src, dst, BlendMode::Normal), 0xFF80_007F);
+        assert_eq!(blend(src, dst, BlendMode::Quarter), 0xFF40_00BF);
+        assert_eq!(blend(src, dst, BlendMode::Add), 0xFFFF_00FF);
+        assert_eq!(blend(0xFF80_FF40, 0xFF80_8080, BlendMode::Multiply), 0xFF40_8020);
+    }
+}
@@ -0,0 +1,314 @@
+// Movies: a recording of the keypad state of every frame, which replays a
+// run exactly.
+//
+// A movie starts from a save state, which also fixes the random seed, and
+// holds the input of each frame after it. Every `CHECK_INTERVAL` frames, and
+// once at the end, it also records a hash of the machine state so a replay
+// that goes differently (a changed emulator or ROM) is noticed where it
+// happens instead of just ending up somewhere else.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::chip8::Chip8;
+use crate::input::InputMap;
+use crate::savestate::{self, StateError, StateReader, StateWriter};
+
+const MAGIC: &[u8; 8] = b"RCHIP8MV";
+pub const MOVIE_VERSION: u16 = 1;
+// Frames between state hashes, one per second.
+pub const CHECK_INTERVAL: usize = 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    // The movie file couldn't be read or written.
+    Io(io::Error),
+    // The file doesn't start with the movie magic.
+    NotAMovie,
+    // The file was written by a different version of the format.
+    UnsupportedVersion(u16),
+    // The movie was recorded while running a different ROM.
+    RomMismatch,
+    // The movie or its starting state is damaged.
+    State(StateError),
+    // The machine doesn't match the recording at this frame.
+    Desync { frame: usize, expected: u64, found: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Io(error) => write!(f, "could not access movie: {}", error),
+            MovieError::NotAMovie => write!(f, "not a movie file"),
+            MovieError::UnsupportedVersion(version) => write!(f, "unsupported movie version {} (expected {})", version, MOVIE_VERSION),
+            MovieError::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::State(error) => write!(f, "damaged movie: {}", error),
+            MovieError::Desync { frame, expected, found } => write!(f, "replay desynced at frame {}: state hash {:016X}, recorded {:016X}", frame, found, expected),
+        }
+    }
+}
+
+impl Error for MovieError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MovieError::Io(error) => Some(error),
+            MovieError::State(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MovieError {
+    fn from(error: io::Error) -> MovieError {
+        MovieError::Io(error)
+    }
+}
+
+impl From<StateError> for MovieError {
+    fn from(error: StateError) -> MovieError {
+        MovieError::State(error)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u64,
+    // Seed of the random number generator at the start, also part of the
+    // starting state but kept separately to be easy to find.
+    seed: u64,
+    ips: u32,
+    // Compressed save state the movie starts from.
+    start: Vec<u8>,
+    inputs: Vec<InputMap>,
+    // State hashes, taken before running the frame.
+    checkpoints: Vec<(usize, u64)>,
+}
+
+impl Movie {
+    // Start recording from the current state of the machine.
+    pub fn record(chip8: &Chip8) -> Movie {
+        Movie {
+            rom_hash: chip8.rom.hash(),
+            seed: chip8.cpu.rng.seed(),
+            ips: chip8.ips,
+            start: savestate::compress(&savestate::save_state(chip8)),
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    // Record the machine's input before it runs a frame.
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        if self.inputs.len().is_multiple_of(CHECK_INTERVAL) {
+            self.checkpoints.push((self.inputs.len(), savestate::state_hash(chip8)));
+        }
+        self.inputs.push(chip8.input);
+    }
+
+    // Record the state the movie ends in.
+    pub fn finish(&mut self, chip8: &Chip8) {
+        if self.checkpoints.last().map(|&(frame, _)| frame) != Some(self.inputs.len()) {
+            self.checkpoints.push((self.inputs.len(), savestate::state_hash(chip8)));
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Number of frames recorded.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        out.raw(MAGIC);
+        out.u16(MOVIE_VERSION);
+        out.u64(self.rom_hash);
+        out.u64(self.seed);
+        out.u32(self.ips);
+        out.bytes(&self.start);
+        out.u32(self.inputs.len() as u32);
+        for input in self.inputs.iter() {
+            out.u32(input.to_bits());
+        }
+        out.u32(self.checkpoints.len() as u32);
+        for &(frame, hash) in self.checkpoints.iter() {
+            out.u32(frame as u32);
+            out.u64(hash);
+        }
+        out.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, MovieError> {
+        let mut input = StateReader::new(data);
+        let mut magic = [0; 8];
+        input.raw(&mut magic).map_err(|_| MovieError::NotAMovie)?;
+        if &magic != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = input.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = input.u64()?;
+        let seed = input.u64()?;
+        let ips = input.u32()?;
+        if ips == 0 {
+            return Err(StateError::Invalid("speed").into());
+        }
+        let start = input.bytes()?;
+        let mut inputs = Vec::new();
+        for _ in 0..input.u32()? {
+            inputs.push(InputMap::from_bits(input.u32()?));
+        }
+        let mut checkpoints = Vec::new();
+        for _ in 0..input.u32()? {
+            checkpoints.push((input.u32()? as usize, input.u64()?));
+        }
+        if !input.is_empty() {
+            return Err(StateError::Invalid("length").into());
+        }
+        Ok(Movie { rom_hash, seed, ips, start, inputs, checkpoints })
+    }
+
+    pub fn save_file(&self, path: &str) -> Result<(), MovieError> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_file(path: &str) -> Result<Movie, MovieError> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
+
+// A movie being played back.
+pub struct Replay {
+    movie: Movie,
+    // Next frame to play.
+    frame: usize,
+    // Next checkpoint to compare against.
+    checkpoint: usize,
+}
+
+impl Replay {
+    // Put the machine into the movie's starting state.
+    pub fn start(movie: Movie, chip8: &mut Chip8) -> Result<Replay, MovieError> {
+        if movie.rom_hash != chip8.rom.hash() {
+            return Err(MovieError::RomMismatch);
+        }
+        savestate::load_state(chip8, &savestate::decompress(&movie.start)?)?;
+        chip8.ips = movie.ips;
+        Ok(Replay { movie, frame: 0, checkpoint: 0 })
+    }
+
+    // Number of frames played so far.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.len()
+    }
+
+    // Check the machine against the recording and set the input for the
+    // next frame. Returns false once the movie is over, after checking the
+    // final state.
+    pub fn next_frame(&mut self, chip8: &mut Chip8) -> Result<bool, MovieError> {
+        // The recorded hashes include the input of the frame
+        let input = self.movie.inputs.get(self.frame).copied();
+        if let Some(input) = input {
+            chip8.input = input;
+        }
+        if let Some(&(frame, expected)) = self.movie.checkpoints.get(self.checkpoint) {
+            if frame == self.frame {
+                self.checkpoint += 1;
+                let found = savestate::state_hash(chip8);
+                if found != expected {
+                    return Err(MovieError::Desync { frame, expected, found });
+                }
+            }
+        }
+        if input.is_none() {
+            return Ok(false);
+        }
+        self.frame += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::{Random, RandomMode};
+    use crate::variant::Variant;
+
+    // Draws a random sprite whenever key 5 is held.
+    const PROGRAM: [u8; 14] = [0x65, 0x05, 0xE5, 0x9E, 0x12, 0x00, 0xC0, 0x3F, 0xC1, 0x1F, 0xD0, 0x15, 0x12, 0x00];
+
+    fn machine(seed: u64) -> Chip8 {
+        let mut chip8 = Chip8::from_program(&PROGRAM, Variant::Chip8);
+        chip8.cpu.rng = Random::new(seed, RandomMode::Splitmix);
+        chip8
+    }
+
+    fn recording(chip8: &mut Chip8, frames: usize) -> Movie {
+        let mut movie = Movie::record(chip8);
+        for frame in 0..frames {
+            chip8.input.set_key(5, frame % 7 < 3);
+            movie.record_frame(chip8);
+            chip8.run_frame().unwrap();
+        }
+        movie.finish(chip8);
+        movie
+    }
+
+    #[test]
+    fn replay_reproduces_run() {
+        let mut recorded = machine(1);
+        let movie = Movie::from_bytes(&recording(&mut recorded, 150).to_bytes()).unwrap();
+        assert_eq!(movie.seed(), 1);
+
+        // A different seed is replaced by the movie's
+        let mut replayed = machine(2);
+        let mut replay = Replay::start(movie, &mut replayed).unwrap();
+        while replay.next_frame(&mut replayed).unwrap() {
+            replayed.run_frame().unwrap();
+        }
+        assert_eq!(replay.frame(), 150);
+        assert_eq!(savestate::save_state(&replayed), savestate::save_state(&recorded));
+    }
+
+    #[test]
+    fn replay_detects_desync() {
+        let mut recorded = machine(1);
+        let movie = recording(&mut recorded, 150);
+        let mut replayed = machine(1);
+        let mut replay = Replay::start(movie, &mut replayed).unwrap();
+        let result = loop {
+            match replay.next_frame(&mut replayed) {
+                Ok(true) => {
+                    // Something the recording didn't do
+                    replayed.cpu.V[0xE] = 1;
+                    replayed.run_frame().unwrap();
+                }
+                other => break other,
+            }
+        };
+        assert!(matches!(result, Err(MovieError::Desync { frame: CHECK_INTERVAL, .. })));
+    }
+
+    #[test]
+    fn replay_rejects_other_rom() {
+        let movie = recording(&mut machine(1), 10);
+        let mut other = Chip8::from_program(&[0x12, 0x00], Variant::Chip8);
+        assert!(matches!(Replay::start(movie, &mut other), Err(MovieError::RomMismatch)));
+    }
+}
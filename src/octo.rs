@@ -0,0 +1,917 @@
+// Compiler for Octo, the structured assembly language most modern CHIP-8,
+// SUPER-CHIP and XO-CHIP programs are written in.
+//
+//     :const SPEED 2
+//     :alias x v0
+//     : main
+//         x := 0
+//         loop
+//             i := ball
+//             sprite x x 4
+//             x += SPEED
+//             if x == 60 then x := 0
+//         again
+//     : ball 0x60 0xF0 0xF0 0x60
+//
+// A program is a list of whitespace separated tokens, `#` starts a comment.
+// Statements follow the Octo manual: register assignments (`:=`, `+=`,
+// `-=`, `=-`, `|=`, `&=`, `^=`, `>>=`, `<<=`), the `i` forms, `if ... then`,
+// `if ... begin ... else ... end`, `loop ... while ... again`, `:macro`,
+// `:calc`, `:alias`, `:const`, `:unpack`, `:next`, `:byte` and `:org`. A bare
+// label name calls it, a bare number is a data byte. If the program doesn't
+// start with `: main`, it starts with a jump there.
+//
+// `:calc` expressions are evaluated right to left without precedence, like
+// Octo does, so `2 * 3 + 1` is 8. Use parentheses to group.
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::asm::{AsmError, Assembly};
+
+const PROGRAM_START: u32 = 0x200;
+const MEMORY_SIZE: u32 = 0x10000;
+// Macros expanding into more macros than this are assumed to be recursive.
+const MAX_EXPANSIONS: usize = 100_000;
+
+// Words that can't be used as names.
+const KEYWORDS: &[&str] = &[
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "-", "{", "}", "(", ")", "key", "-key",
+    "hex", "bighex", "random", "delay", "buzzer", "pitch", "i", "long", "if", "then", "begin", "else", "end", "loop", "while", "again",
+    "clear", "return", ";", "bcd", "save", "load", "sprite", "jump", "jump0", "native", "saveflags", "loadflags", "hires", "lores",
+    "exit", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "plane", "audio",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+// Addresses filled in once every label is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FixupKind {
+    // The low 12 bits of an instruction.
+    Address,
+    // A whole 16 bit word, for `i := long`.
+    Long,
+    // The byte of `v0 := nibble << 4 | high address bits` of `:unpack`.
+    UnpackHigh(u8),
+    // The byte of `v1 := low address byte` of `:unpack`.
+    UnpackLow,
+}
+
+struct Fixup {
+    addr: u32,
+    kind: FixupKind,
+    name: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+enum Block {
+    // `if ... begin`, with the address of the jump to the else branch.
+    If(u32),
+    // `else`, with the address of the jump over it.
+    Else(u32),
+    // `loop`, with its start and the jumps out of it of each `while`.
+    Loop(u32, Vec<u32>),
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    pos: usize,
+    // Line of the statement being compiled.
+    line: usize,
+    here: u32,
+    rom: Vec<u8>,
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    expansions: usize,
+}
+
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        tokens.extend(code.split_whitespace().map(|text| Token { text: text.to_string(), line: index + 1 }));
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return digits.parse::<f64>().ok().map(|value| if negative { -value } else { value });
+    } else {
+        return None;
+    };
+    Some(if negative { -value as f64 } else { value as f64 })
+}
+
+fn register_name(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    match u8::from_str_radix(digit, 16) {
+        Ok(reg) if digit.len() == 1 => Some(reg),
+        _ => None,
+    }
+}
+
+fn is_reserved(name: &str) -> bool {
+    KEYWORDS.contains(&name) || register_name(name).is_some() || name.starts_with(':') || parse_number(name).is_some()
+}
+
+fn to_integer(value: f64) -> i64 {
+    value.floor() as i64
+}
+
+// Control structures are built from jumps, which only reach 12 bit
+// addresses.
+fn jump_to(addr: u32) -> Result<u16, String> {
+    if addr > 0xFFF {
+        return Err(format!("control structure at #{:X} is out of reach of a jump", addr));
+    }
+    Ok(0x1000 | addr as u16)
+}
+
+// The skip with the opposite condition.
+fn negate_skip(opcode: u16) -> u16 {
+    match opcode >> 12 {
+        0x3 => opcode + 0x1000,
+        0x4 => opcode - 0x1000,
+        0x5 => opcode + 0x4000,
+        0x9 => opcode - 0x4000,
+        // Ex9E and ExA1
+        _ => opcode ^ (0x9E ^ 0xA1),
+    }
+}
+
+impl Compiler {
+    fn new(source: &str) -> Compiler {
+        Compiler {
+            tokens: tokenize(source),
+            pos: 0,
+            line: 1,
+            here: PROGRAM_START,
+            rom: Vec::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.get(self.pos).ok_or("unexpected end of program")?;
+        self.pos += 1;
+        Ok(token.text.clone())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("expected {}, found {}", expected, token));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= MEMORY_SIZE {
+            return Err("program doesn't fit in memory".to_string());
+        }
+        let offset = (self.here - PROGRAM_START) as usize;
+        if self.rom.len() <= offset {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), String> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    // Point the jump at `addr` to the current address.
+    fn patch_jump(&mut self, addr: u32) -> Result<(), String> {
+        let jump = jump_to(self.here)?;
+        let offset = (addr - PROGRAM_START) as usize;
+        self.rom[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
+    }
+
+    fn define(&self, name: &str) -> Result<(), String> {
+        if is_reserved(name) {
+            return Err(format!("{} can't be used as a name", name));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) || self.aliases.contains_key(name) || self.macros.contains_key(name) {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.as_register(&token).ok_or(format!("expected a register, found {}", token))
+    }
+
+    fn as_register(&self, token: &str) -> Option<u8> {
+        register_name(token).or_else(|| self.aliases.get(token).copied())
+    }
+
+    fn value_of(&self, token: &str) -> Option<f64> {
+        parse_number(token).or_else(|| self.constants.get(token).copied()).or_else(|| self.labels.get(token).map(|&addr| addr as f64))
+    }
+
+    fn value(&mut self) -> Result<i64, String> {
+        let token = self.next()?;
+        self.value_of(&token).map(to_integer).ok_or(format!("undefined name {}", token))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let value = self.value()?;
+        if !(-128..=255).contains(&value) {
+            return Err(format!("byte {} out of range -128-255", value));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        let value = self.value()?;
+        if !(0..=15).contains(&value) {
+            return Err(format!("value {} out of range 0-15", value));
+        }
+        Ok(value as u16)
+    }
+
+    // An address operand, which may be a label defined later. Returns the
+    // address if it's known already.
+    fn address(&mut self, at: u32, kind: FixupKind) -> Result<u32, String> {
+        let token = self.next()?;
+        if let Some(value) = self.value_of(&token) {
+            let value = to_integer(value);
+            let max = if kind == FixupKind::Long { 0xFFFF } else { 0xFFF };
+            if !(0..=max).contains(&value) {
+                return Err(format!("address {} out of range 0-{}", value, max));
+            }
+            return Ok(value as u32);
+        }
+        if is_reserved(&token) {
+            return Err(format!("expected an address, found {}", token));
+        }
+        self.fixups.push(Fixup { addr: at, kind, name: token, line: self.line });
+        Ok(0)
+    }
+
+    // Emit an instruction with an address in its low 12 bits.
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let addr = self.address(self.here, FixupKind::Address)?;
+        self.emit(opcode | addr as u16)
+    }
+
+    // Tokens up to the matching `}`, after the opening `{`.
+    fn braced(&mut self) -> Result<Vec<Token>, String> {
+        self.expect("{")?;
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.tokens.get(self.pos).ok_or("missing }")?.clone();
+            self.pos += 1;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    // Opcodes ending with a skip of the next instruction when the
+    // condition is false.
+    fn condition(&mut self) -> Result<Vec<u16>, String> {
+        let x = self.register()? as u16;
+        let operator = self.next()?;
+        let opcodes = match operator.as_str() {
+            "key" => vec![0xE0A1 | x << 8],
+            "-key" => vec![0xE09E | x << 8],
+            "==" | "!=" => {
+                let token = self.next()?;
+                let opcode = if let Some(y) = self.as_register(&token) {
+                    0x9000 | x << 8 | (y as u16) << 4
+                } else {
+                    self.pos -= 1;
+                    0x4000 | x << 8 | self.byte()? as u16
+                };
+                vec![if operator == "==" { opcode } else { negate_skip(opcode) }]
+            }
+            "<" | ">" | "<=" | ">=" => {
+                // VF is loaded with the difference of the two sides, its
+                // carry flag tells which one is bigger
+                let token = self.next()?;
+                let register = self.as_register(&token);
+                if register.is_none() {
+                    self.pos -= 1;
+                }
+                let y_first = operator == ">" || operator == "<=";
+                let mut opcodes = match register {
+                    // Flag set if x >= y, or y >= x
+                    Some(y) if !y_first => vec![0x8F00 | x << 4, 0x8F05 | (y as u16) << 4],
+                    Some(y) => vec![0x8F00 | (y as u16) << 4, 0x8F05 | x << 4],
+                    None if !y_first => vec![0x6F00 | self.byte()? as u16, 0x8F07 | x << 4],
+                    None => vec![0x6F00 | self.byte()? as u16, 0x8F05 | x << 4],
+                };
+                let strict = operator == "<" || operator == ">";
+                opcodes.push(0x3F00 | strict as u16);
+                opcodes
+            }
+            _ => return Err(format!("unknown comparison {}", operator)),
+        };
+        Ok(opcodes)
+    }
+
+    // Emit a condition's opcodes, skipping when it is true instead.
+    fn negated_condition(&mut self) -> Result<(), String> {
+        let mut opcodes = self.condition()?;
+        let skip = opcodes.pop().unwrap();
+        opcodes.push(negate_skip(skip));
+        opcodes.into_iter().try_for_each(|opcode| self.emit(opcode))
+    }
+
+    // Emit a jump to be patched later, returning its address.
+    fn forward_jump(&mut self) -> Result<u32, String> {
+        let addr = self.here;
+        self.emit(0x1000)?;
+        Ok(addr)
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let x = x as u16;
+        let operator = self.next()?;
+        let token = self.next()?;
+        let y = self.as_register(&token).map(|y| (y as u16) << 4);
+        let opcode = match (operator.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y,
+            ("|=", Some(y)) => 0x8001 | y,
+            ("&=", Some(y)) => 0x8002 | y,
+            ("^=", Some(y)) => 0x8003 | y,
+            ("+=", Some(y)) => 0x8004 | y,
+            ("-=", Some(y)) => 0x8005 | y,
+            (">>=", Some(y)) => 0x8006 | y,
+            ("=-", Some(y)) => 0x8007 | y,
+            ("<<=", Some(y)) => 0x800E | y,
+            (":=", None) if token == "delay" => 0xF007,
+            (":=", None) if token == "key" => 0xF00A,
+            (":=", None) if token == "random" => 0xC000 | self.byte()? as u16,
+            (":=", None) | ("+=", None) | ("-=", None) => {
+                self.pos -= 1;
+                let byte = self.byte()?;
+                match operator.as_str() {
+                    ":=" => 0x6000 | byte as u16,
+                    "+=" => 0x7000 | byte as u16,
+                    _ => 0x7000 | byte.wrapping_neg() as u16,
+                }
+            }
+            (_, None) if [":=", "|=", "&=", "^=", "+=", "-=", ">>=", "=-", "<<="].contains(&operator.as_str()) => {
+                return Err(format!("{} needs a register, found {}", operator, token));
+            }
+            _ => return Err(format!("unknown operator {}", operator)),
+        };
+        self.emit(opcode | x << 8)
+    }
+
+    fn i_statement(&mut self) -> Result<(), String> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit(0xF01E | x << 8)
+            }
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.pos += 1;
+                    let x = self.register()? as u16;
+                    self.emit(0xF029 | x << 8)
+                }
+                Some("bighex") => {
+                    self.pos += 1;
+                    let x = self.register()? as u16;
+                    self.emit(0xF030 | x << 8)
+                }
+                Some("long") => {
+                    self.pos += 1;
+                    self.emit(0xF000)?;
+                    let addr = self.address(self.here, FixupKind::Long)?;
+                    self.emit(addr as u16)
+                }
+                _ => self.address_instruction(0xA000),
+            },
+            _ => Err(format!("unknown operator i {}", operator)),
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("macro {} expands forever", name));
+        }
+        let count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for index in 0..count {
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[index].clone(), arg);
+        }
+        let line = self.line;
+        let body: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|token| Token { text: args.get(&token.text).unwrap_or(&token.text).clone(), line })
+            .collect();
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    // Right to left, without precedence. Unary operators apply to
+    // everything on their right, `- 2 + 3` is -5.
+    fn calc(&self, tokens: &[Token], pos: &mut usize) -> Result<f64, String> {
+        let token = tokens.get(*pos).ok_or("incomplete expression")?.text.as_str();
+        *pos += 1;
+        let left = match token {
+            "(" => {
+                let value = self.calc(tokens, pos)?;
+                if tokens.get(*pos).map(|token| token.text.as_str()) != Some(")") {
+                    return Err("missing )".to_string());
+                }
+                *pos += 1;
+                value
+            }
+            "-" => -self.calc(tokens, pos)?,
+            "~" => !to_integer(self.calc(tokens, pos)?) as f64,
+            "!" => (self.calc(tokens, pos)? == 0.0) as i64 as f64,
+            "sin" => self.calc(tokens, pos)?.sin(),
+            "cos" => self.calc(tokens, pos)?.cos(),
+            "tan" => self.calc(tokens, pos)?.tan(),
+            "exp" => self.calc(tokens, pos)?.exp(),
+            "log" => self.calc(tokens, pos)?.ln(),
+            "abs" => self.calc(tokens, pos)?.abs(),
+            "sqrt" => self.calc(tokens, pos)?.sqrt(),
+            "sign" => self.calc(tokens, pos)?.signum(),
+            "ceil" => self.calc(tokens, pos)?.ceil(),
+            "floor" => self.calc(tokens, pos)?.floor(),
+            // The byte already compiled at an address
+            "@" => {
+                let addr = to_integer(self.calc(tokens, pos)?);
+                let offset = addr - PROGRAM_START as i64;
+                if offset < 0 {
+                    return Err(format!("@ {} is before the program", addr));
+                }
+                self.rom.get(offset as usize).copied().unwrap_or(0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => self.value_of(token).ok_or(format!("undefined name {}", token))?,
+        };
+        let operator = match tokens.get(*pos) {
+            Some(token) if token.text != ")" => token.text.as_str(),
+            _ => return Ok(left),
+        };
+        *pos += 1;
+        let right = self.calc(tokens, pos)?;
+        let (a, b) = (to_integer(left), to_integer(right));
+        Ok(match operator {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" | "%" if right == 0.0 => return Err("division by zero".to_string()),
+            "/" => left / right,
+            "%" => left % right,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(format!("unknown operator {}", operator)),
+        })
+    }
+
+    fn calc_block(&mut self) -> Result<f64, String> {
+        let tokens = self.braced()?;
+        let mut pos = 0;
+        let value = self.calc(&tokens, &mut pos)?;
+        if pos != tokens.len() {
+            return Err(format!("unexpected {} in expression", tokens[pos].text));
+        }
+        Ok(value)
+    }
+
+    fn directive(&mut self, directive: &str) -> Result<(), String> {
+        match directive {
+            ":" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                self.labels.insert(name, self.here);
+            }
+            ":next" => {
+                // The second byte of the next instruction, for self-modifying
+                // code
+                let name = self.next()?;
+                self.define(&name)?;
+                self.labels.insert(name, self.here + 1);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let token = self.next()?;
+                let value = self.value_of(&token).ok_or(format!("undefined name {}", token))?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                // Calculations may be repeated to update a value
+                if !self.constants.contains_key(&name) {
+                    self.define(&name)?;
+                }
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let reg = self.register()?;
+                self.aliases.insert(name, reg);
+            }
+            ":macro" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let mut params = Vec::new();
+                while self.peek().is_some_and(|token| token != "{") {
+                    params.push(self.next()?);
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") { to_integer(self.calc_block()?) } else { self.value()? };
+                if !(-128..=255).contains(&value) {
+                    return Err(format!("byte {} out of range -128-255", value));
+                }
+                self.emit_byte(value as u8)?;
+            }
+            ":org" => {
+                let addr = if self.peek() == Some("{") { to_integer(self.calc_block()?) } else { self.value()? };
+                if addr < PROGRAM_START as i64 || addr >= MEMORY_SIZE as i64 {
+                    return Err(format!("org {} outside of the program", addr));
+                }
+                self.here = addr as u32;
+            }
+            ":unpack" => {
+                let nibble = self.nibble()? as u8;
+                let addr = self.address(self.here, FixupKind::UnpackHigh(nibble))?;
+                self.emit(0x6000 | (nibble as u16) << 4 | (addr >> 8) as u16)?;
+                self.pos -= 1;
+                let addr = self.address(self.here, FixupKind::UnpackLow)?;
+                self.emit(0x6100 | (addr & 0xFF) as u16)?;
+            }
+            ":assert" => {
+                if self.calc_block()? == 0.0 {
+                    return Err("assertion failed".to_string());
+                }
+            }
+            // Debugger hints, nothing to compile
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            _ => return Err(format!("unknown directive {}", directive)),
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        self.line = self.tokens[self.pos].line;
+        let token = self.next()?;
+        if token.starts_with(':') {
+            return self.directive(&token);
+        }
+        if let Some(x) = self.as_register(&token) {
+            return self.assignment(x);
+        }
+        match token.as_str() {
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.emit(0x00C0 | n)
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.emit(0x00D0 | n)
+            }
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "plane" => {
+                let n = self.nibble()?;
+                self.emit(0xF001 | n << 8)
+            }
+            "audio" => self.emit(0xF002),
+            "jump" => self.address_instruction(0x1000),
+            "jump0" => self.address_instruction(0xB000),
+            "native" => self.address_instruction(0x0000),
+            "sprite" => {
+                let (x, y) = (self.register()? as u16, self.register()? as u16);
+                let n = self.nibble()?;
+                self.emit(0xD000 | x << 8 | y << 4 | n)
+            }
+            "bcd" | "save" | "load" | "saveflags" | "loadflags" => {
+                let x = self.register()? as u16;
+                // XO-CHIP register ranges
+                if (token == "save" || token == "load") && self.peek() == Some("-") {
+                    self.pos += 1;
+                    let y = self.register()? as u16;
+                    return self.emit(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                }
+                let low = match token.as_str() {
+                    "bcd" => 0x33,
+                    "save" => 0x55,
+                    "load" => 0x65,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.emit(0xF000 | x << 8 | low)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                let low = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low)
+            }
+            "i" => self.i_statement(),
+            "if" => {
+                // `then` skips the next statement, `begin` jumps to `else`
+                let mut opcodes = self.condition()?;
+                match self.next()?.as_str() {
+                    "then" => opcodes.into_iter().try_for_each(|opcode| self.emit(opcode)),
+                    "begin" => {
+                        let skip = opcodes.pop().unwrap();
+                        opcodes.push(negate_skip(skip));
+                        opcodes.into_iter().try_for_each(|opcode| self.emit(opcode))?;
+                        let jump = self.forward_jump()?;
+                        self.blocks.push(Block::If(jump));
+                        Ok(())
+                    }
+                    other => Err(format!("expected then or begin, found {}", other)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If(jump)) => {
+                    let over = self.forward_jump()?;
+                    self.patch_jump(jump)?;
+                    self.blocks.push(Block::Else(over));
+                    Ok(())
+                }
+                _ => Err("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If(jump)) | Some(Block::Else(jump)) => {
+                    self.patch_jump(jump)?;
+                    Ok(())
+                }
+                _ => Err("end without if ... begin".to_string()),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop(self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                self.negated_condition()?;
+                let jump = self.forward_jump()?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop(..))) {
+                    Some(Block::Loop(_, whiles)) => {
+                        whiles.push(jump);
+                        Ok(())
+                    }
+                    _ => Err("while outside of a loop".to_string()),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop(start, whiles)) => {
+                    self.emit(jump_to(start)?)?;
+                    for jump in whiles {
+                        self.patch_jump(jump)?;
+                    }
+                    Ok(())
+                }
+                _ => Err("again without loop".to_string()),
+            },
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token),
+            _ => {
+                // Data bytes and calls
+                self.pos -= 1;
+                if parse_number(&token).is_some() || self.constants.contains_key(&token) {
+                    let byte = self.byte()?;
+                    return self.emit_byte(byte);
+                }
+                if is_reserved(&token) {
+                    return Err(format!("unexpected {}", token));
+                }
+                self.address_instruction(0x2000)
+            }
+        }
+    }
+
+    fn resolve(&mut self) -> Result<(), AsmError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let error = |message: String| AsmError { file: String::new(), line: fixup.line, message };
+            let addr = *self.labels.get(&fixup.name).ok_or_else(|| error(format!("undefined name {}", fixup.name)))?;
+            let offset = (fixup.addr - PROGRAM_START) as usize;
+            match fixup.kind {
+                FixupKind::Address | FixupKind::UnpackHigh(_) if addr > 0xFFF => {
+                    return Err(error(format!("{} at #{:X} is out of reach, use i := long", fixup.name, addr)));
+                }
+                FixupKind::Address => {
+                    self.rom[offset] |= (addr >> 8) as u8;
+                    self.rom[offset + 1] = addr as u8;
+                }
+                FixupKind::Long => self.rom[offset..offset + 2].copy_from_slice(&(addr as u16).to_be_bytes()),
+                FixupKind::UnpackHigh(nibble) => self.rom[offset + 1] = nibble << 4 | (addr >> 8) as u8,
+                FixupKind::UnpackLow => self.rom[offset + 1] = addr as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<(), AsmError> {
+        // Programs start at main, jump there unless it comes first
+        let has_main = self.tokens.windows(2).any(|pair| pair[0].text == ":" && pair[1].text == "main");
+        let main_first = self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if has_main && !main_first {
+            self.emit(0x1000).unwrap();
+            self.fixups.push(Fixup { addr: PROGRAM_START, kind: FixupKind::Address, name: "main".to_string(), line: 1 });
+        }
+        while !self.is_done() {
+            self.statement().map_err(|message| AsmError { file: String::new(), line: self.line, message })?;
+        }
+        if let Some(block) = self.blocks.last() {
+            let missing = if let Block::Loop(..) = block { "again" } else { "end" };
+            return Err(AsmError { file: String::new(), line: self.line, message: format!("missing {}", missing) });
+        }
+        self.resolve()
+    }
+}
+
+// Compile Octo source. `name` identifies it in errors.
+pub fn compile(name: &str, source: &str) -> Result<Assembly, AsmError> {
+    let mut compiler = Compiler::new(source);
+    compiler.compile().map_err(|error| AsmError { file: name.to_string(), ..error })?;
+    let mut symbols: Vec<(String, u32)> = compiler.labels.into_iter().collect();
+    symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    Ok(Assembly { rom: compiler.rom, symbols })
+}
+
+pub fn compile_file(path: &str) -> Result<Assembly, AsmError> {
+    let source = fs::read_to_string(path).map_err(|error| AsmError { file: path.to_string(), line: 0, message: error.to_string() })?;
+    compile(path, &source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::variant::Variant;
+
+    fn run(source: &str) -> Chip8 {
+        let assembly = compile("test.8o", source).unwrap();
+        let mut chip8 = Chip8::from_program(&assembly.rom, Variant::SuperChip);
+        for _ in 0..10_000 {
+            if chip8.has_exited() {
+                return chip8;
+            }
+            chip8.step().unwrap();
+        }
+        panic!("program didn't exit");
+    }
+
+    #[test]
+    fn compiles_statements() {
+        let source = "
+            : main
+                v1 := 5  v1 += v2  v1 -= 1  i := ball  sprite v1 v2 4
+                if v1 != 3 then v3 := key
+                jump main
+            : ball 0x60 -1 0b11110000
+        ";
+        let assembly = compile("test.8o", source).unwrap();
+        let expected = [0x61, 0x05, 0x81, 0x24, 0x71, 0xFF, 0xA2, 0x10, 0xD1, 0x24, 0x31, 0x03, 0xF3, 0x0A, 0x12, 0x00, 0x60, 0xFF, 0xF0];
+        assert_eq!(assembly.rom, expected);
+        assert_eq!(assembly.symbols, [("main".to_string(), 0x200), ("ball".to_string(), 0x210)]);
+    }
+
+    #[test]
+    fn runs_structured_code() {
+        let chip8 = run("
+            :macro add-twice reg n { reg += n reg += n }
+            :const LIMIT 10
+            :calc DOUBLE { LIMIT * 2 }
+            :alias count v0
+            : table 0x42
+            : main
+                count := 0
+                loop
+                    while count < LIMIT
+                    add-twice v1 1
+                    count += 1
+                again
+                if v1 == DOUBLE begin v2 := 1 else v2 := 2 end
+                if count >= 10 then v3 := 7
+                if count > 10 then v3 := 9
+                v4 := 3
+                triple-v4
+                i := table
+                load v0
+                exit
+            : triple-v4
+                v5 := v4 v4 += v5 v4 += v5
+            ;
+        ");
+        assert_eq!(chip8.cpu.V[..6], [0x42, 20, 1, 7, 9, 3]);
+    }
+
+    #[test]
+    fn unpacks_and_labels_operands() {
+        let source = ":unpack 0xA data\n: main\n:next target v0 := 0\ni := long data\n: data";
+        let assembly = compile("test.8o", source).unwrap();
+        // Main doesn't come first, so there is a jump to it
+        assert_eq!(assembly.rom, [0x12, 0x06, 0x60, 0xA2, 0x61, 0x0C, 0x60, 0x00, 0xF0, 0x00, 0x02, 0x0C]);
+        assert!(assembly.symbols.contains(&("target".to_string(), 0x207)));
+        assert_eq!(compile("test.8o", ": main 0x12").unwrap().rom, [0x12]);
+    }
+
+    #[test]
+    fn calc_evaluates_right_to_left() {
+        let assembly = compile("test.8o", ":calc a { 2 * 3 + 1 }\n:calc b { ( 2 * 3 ) + 1 }\n:byte a :byte b :byte { - 2 + 3 }").unwrap();
+        assert_eq!(assembly.rom, [8, 7, (-5i8) as u8]);
+    }
+
+    #[test]
+    fn reports_errors_with_location() {
+        let error = |source: &str| compile("test.8o", source).unwrap_err();
+        assert_eq!(error(": main\n  jump nowhere").to_string(), "test.8o:2: undefined name nowhere");
+        assert_eq!(error("loop v0 += 1").message, "missing again");
+        assert_eq!(error(": main\n: main").message, "main is already defined");
+        assert_eq!(error(": loop").message, "loop can't be used as a name");
+        assert_eq!(error("v0 := 300").message, "byte 300 out of range -128-255");
+        assert_eq!(error("v0 |= 3").message, "|= needs a register, found 3");
+        assert_eq!(error("else").message, "else without if ... begin");
+    }
+}
@@ -0,0 +1,180 @@
+// Behaviour that differs between CHIP-8 interpreters. ROMs were written
+// against whichever interpreter their authors had, so running them correctly
+// means picking the matching set of quirks.
+
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// How Fx55 and Fx65 leave I after accessing memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    // I is not changed (SUPER-CHIP, most modern interpreters).
+    Unchanged,
+    // I = I + x (CHIP-48).
+    ByX,
+    // I = I + x + 1 (COSMAC VIP).
+    ByXPlusOne,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    // What Fx55/Fx65 do to I.
+    pub index_increment: IndexIncrement,
+    // Bnnn behaves as Bxnn and jumps to xnn + Vx instead of nnn + V0.
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    // DRW waits for the next frame, so at most one sprite is drawn per frame.
+    pub display_wait: bool,
+    // Sprites are clipped at the screen edge instead of wrapping around.
+    pub clip_sprites: bool,
+}
+
+// Names accepted by `Quirks::preset`.
+pub const PRESETS: [&str; 5] = ["vip", "chip48", "schip", "xochip", "modern"];
+
+impl Quirks {
+    // The original COSMAC VIP interpreter.
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            display_wait: true,
+            clip_sprites: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1.
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: true,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo.
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            index_increment: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: false,
+        }
+    }
+
+    // What Cowgod's reference describes and most games written for modern
+    // interpreters expect.
+    pub fn modern() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            display_wait: false,
+            clip_sprites: true,
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::schip()),
+            "xochip" => Some(Quirks::xochip()),
+            "modern" => Some(Quirks::modern()),
+            _ => None,
+        }
+    }
+
+    // Override a single quirk from a `name=value` string, e.g. `shift=on` or
+    // `memory=x+1`.
+    pub fn set(&mut self, setting: &str) -> Result<(), String> {
+        let mut parts = setting.splitn(2, '=');
+        let name = parts.next().unwrap_or("");
+        let value = parts.next().ok_or(format!("quirk setting {} is not name=value", setting))?;
+        let invalid = || format!("invalid value for quirk {}: {}", name, value);
+
+        if name == "memory" {
+            self.index_increment = match value {
+                "off" => IndexIncrement::Unchanged,
+                "x" => IndexIncrement::ByX,
+                "x+1" => IndexIncrement::ByXPlusOne,
+                _ => return Err(invalid()),
+            };
+            return Ok(());
+        }
+
+        let enabled = match value {
+            "on" | "true" | "1" => true,
+            "off" | "false" | "0" => false,
+            _ => return Err(invalid()),
+        };
+        match name {
+            "shift" => self.shift_uses_vy = enabled,
+            "jump" => self.jump_uses_vx = enabled,
+            "vf-reset" => self.logic_resets_vf = enabled,
+            "display-wait" => self.display_wait = enabled,
+            "clip" => self.clip_sprites = enabled,
+            _ => return Err(format!("unknown quirk {}", name)),
+        }
+        Ok(())
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::modern()
+    }
+}
+
+impl Snapshot for Quirks {
+    fn save(&self, out: &mut StateWriter) {
+        out.bool(self.shift_uses_vy);
+        out.u8(match self.index_increment {
+            IndexIncrement::Unchanged => 0,
+            IndexIncrement::ByX => 1,
+            IndexIncrement::ByXPlusOne => 2,
+        });
+        out.bool(self.jump_uses_vx);
+        out.bool(self.logic_resets_vf);
+        out.bool(self.display_wait);
+        out.bool(self.clip_sprites);
+    }
+
+    fn load(input: &mut StateReader) -> Result<Quirks, StateError> {
+        Ok(Quirks {
+            shift_uses_vy: input.bool()?,
+            index_increment: match input.u8()? {
+                0 => IndexIncrement::Unchanged,
+                1 => IndexIncrement::ByX,
+                2 => IndexIncrement::ByXPlusOne,
+                _ => return Err(StateError::Invalid("index increment quirk")),
+            },
+            jump_uses_vx: input.bool()?,
+            logic_resets_vf: input.bool()?,
+            display_wait: input.bool()?,
+            clip_sprites: input.bool()?,
+        })
+    }
+}
@@ -0,0 +1,82 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// How Cxkk comes up with its random byte.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RandomMode {
+    // A good generator whose values only depend on the seed (splitmix64).
+    #[default]
+    Splitmix,
+}
+
+impl RandomMode {
+    pub fn from_name(name: &str) -> Option<RandomMode> {
+        match name {
+            "splitmix" => Some(RandomMode::Splitmix),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomMode::Splitmix => "splitmix",
+        }
+    }
+}
+
+// Random number generator for Cxkk. It is part of the machine state so saved
+// games and replays continue the same way, and the seed it started from is
+// kept so a run can be repeated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Random {
+    seed: u64,
+    mode: RandomMode,
+    // Splitmix64 state.
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64, mode: RandomMode) -> Random {
+        Random { seed, mode, state: seed }
+    }
+
+    // A generator seeded from the operating system.
+    pub fn from_entropy() -> Random {
+        Random::new(rand::random(), RandomMode::Splitmix)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn mode(&self) -> RandomMode {
+        self.mode
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        match self.mode {
+            RandomMode::Splitmix => (self.next_u64() >> 56) as u8,
+        }
+    }
+}
+
+impl Snapshot for Random {
+    fn save(&self, out: &mut StateWriter) {
+        out.u64(self.seed);
+        out.str(self.mode.name());
+        out.u64(self.state);
+    }
+
+    fn load(input: &mut StateReader) -> Result<Random, StateError> {
+        let seed = input.u64()?;
+        let mode = RandomMode::from_name(&input.string()?).ok_or(StateError::Invalid("random mode"))?;
+        Ok(Random { seed, mode, state: input.u64()? })
+    }
+}
@@ -0,0 +1,208 @@
+// Rewinding: going back through the last few seconds of gameplay.
+//
+// Storing every frame would cost a save state per frame, so only every
+// `KEYFRAME_INTERVAL`th frame is kept, compressed, along with the keypad
+// state of every frame. Going back to a frame restores the keyframe before it
+// and runs the frames in between again with the recorded input. The machine
+// is deterministic (the random number generator is part of its state), so
+// this recreates the frame exactly.
+
+use std::collections::VecDeque;
+use std::mem;
+
+use crate::chip8::{Chip8, TIMER_HZ};
+use crate::fault::CpuFault;
+use crate::input::InputMap;
+use crate::savestate;
+
+// Frames between keyframes. Rewinding a frame runs at most this many frames
+// again, while longer intervals use less memory.
+pub const KEYFRAME_INTERVAL: u64 = 10;
+pub const DEFAULT_SECONDS: u32 = 10;
+pub const DEFAULT_MEMORY_CAP: usize = 64 * 1024 * 1024;
+
+struct Keyframe {
+    // Number of the frame that starts from this state.
+    frame: u64,
+    // Compressed save state.
+    state: Vec<u8>,
+}
+
+pub struct Rewind {
+    // Frames that can be rewound.
+    depth: u64,
+    // Bytes the keyframes and inputs may use.
+    memory_cap: usize,
+    keyframes: VecDeque<Keyframe>,
+    // Input of every frame since the oldest keyframe.
+    inputs: VecDeque<InputMap>,
+    // Number of the next frame to be run.
+    frame: u64,
+    // Bytes currently used.
+    used: usize,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32, memory_cap: usize) -> Rewind {
+        Rewind {
+            depth: seconds as u64 * TIMER_HZ as u64,
+            memory_cap,
+            keyframes: VecDeque::new(),
+            inputs: VecDeque::new(),
+            frame: 0,
+            used: 0,
+        }
+    }
+
+    // Forget everything recorded, e.g. after loading a save state.
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.inputs.clear();
+        self.used = 0;
+    }
+
+    // Seconds of gameplay that can currently be rewound.
+    pub fn seconds(&self) -> f32 {
+        self.inputs.len() as f32 / TIMER_HZ as f32
+    }
+
+    // Record the machine before it runs a frame, with the input it is about
+    // to run it with.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let keyframe_due = match self.keyframes.back() {
+            Some(keyframe) => self.frame >= keyframe.frame + KEYFRAME_INTERVAL,
+            None => true,
+        };
+        if keyframe_due {
+            let state = savestate::compress(&savestate::save_state(chip8));
+            self.used += state.len();
+            self.keyframes.push_back(Keyframe { frame: self.frame, state });
+        }
+        self.inputs.push_back(chip8.input);
+        self.used += mem::size_of::<InputMap>();
+        self.frame += 1;
+        self.evict();
+    }
+
+    // Drop the oldest keyframe, and the inputs only it needs, while the
+    // buffer is deeper or larger than allowed. The newest keyframe is always
+    // kept.
+    fn evict(&mut self) {
+        while self.keyframes.len() > 1 {
+            let oldest = &self.keyframes[0];
+            let next = self.keyframes[1].frame;
+            // Rewinding to any frame from `next` on doesn't need the oldest
+            // keyframe
+            if self.frame - next < self.depth && self.used <= self.memory_cap {
+                break;
+            }
+            self.used -= oldest.state.len();
+            for _ in oldest.frame..next {
+                self.inputs.pop_front();
+                self.used -= mem::size_of::<InputMap>();
+            }
+            self.keyframes.pop_front();
+        }
+    }
+
+    // Put the machine back to the start of the previous frame, which shows
+    // the frame before it. Returns false if there is nothing left to rewind.
+    // The keypad keeps its current state.
+    pub fn step_back(&mut self, chip8: &mut Chip8) -> Result<bool, CpuFault> {
+        let target = match self.frame.checked_sub(1) {
+            Some(target) => target,
+            None => return Ok(false),
+        };
+        let index = match self.keyframes.iter().rposition(|keyframe| keyframe.frame <= target) {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let live_input = chip8.input;
+        let keyframe = &self.keyframes[index];
+        // Keyframes were saved from this machine, so they always load
+        savestate::decompress(&keyframe.state).and_then(|state| savestate::load_state(chip8, &state)).expect("rewind keyframe is a valid save state");
+        let first = self.keyframes[0].frame;
+        for frame in keyframe.frame..target {
+            chip8.input = self.inputs[(frame - first) as usize];
+            chip8.run_frame()?;
+        }
+        chip8.input = live_input;
+
+        // Forget the frames that were rewound, running again records them
+        // afresh
+        self.frame = target;
+        while self.inputs.len() as u64 > target - first {
+            self.inputs.pop_back();
+            self.used -= mem::size_of::<InputMap>();
+        }
+        if self.keyframes.back().is_some_and(|keyframe| keyframe.frame >= target) {
+            let keyframe = self.keyframes.pop_back().unwrap();
+            self.used -= keyframe.state.len();
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // Moves a random sprite around depending on key 5.
+    const PROGRAM: [u8; 16] = [0x00, 0xE0, 0xC0, 0x3F, 0xA0, 0x00, 0x65, 0x05, 0xE5, 0xA1, 0x71, 0x01, 0xD0, 0x15, 0x12, 0x00];
+
+    fn machine() -> Chip8 {
+        Chip8::from_program(&PROGRAM, Variant::Chip8)
+    }
+
+    #[test]
+    fn step_back_recreates_earlier_frames() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(DEFAULT_SECONDS, DEFAULT_MEMORY_CAP);
+        let mut states = Vec::new();
+        for frame in 0..25 {
+            chip8.input.set_key(5, frame % 3 == 0);
+            states.push(savestate::save_state(&chip8));
+            rewind.record(&chip8);
+            chip8.run_frame().unwrap();
+        }
+        for frame in (0..25).rev() {
+            assert!(rewind.step_back(&mut chip8).unwrap());
+            // The keypad isn't rewound
+            chip8.input.set_key(5, frame % 3 == 0);
+            assert_eq!(savestate::save_state(&chip8), states[frame]);
+        }
+        assert!(!rewind.step_back(&mut chip8).unwrap());
+    }
+
+    #[test]
+    fn depth_limits_history() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(1, DEFAULT_MEMORY_CAP);
+        for _ in 0..200 {
+            rewind.record(&chip8);
+            chip8.run_frame().unwrap();
+        }
+        assert!(rewind.seconds() >= 1.0);
+        assert!(rewind.seconds() < 1.0 + KEYFRAME_INTERVAL as f32 / TIMER_HZ as f32);
+        let available = rewind.inputs.len();
+        let mut steps = 0;
+        while rewind.step_back(&mut chip8).unwrap() {
+            steps += 1;
+        }
+        assert_eq!(steps, available);
+    }
+
+    #[test]
+    fn memory_cap_limits_history() {
+        let mut chip8 = machine();
+        let mut rewind = Rewind::new(DEFAULT_SECONDS, 1);
+        for _ in 0..100 {
+            rewind.record(&chip8);
+            chip8.run_frame().unwrap();
+        }
+        // Only the newest keyframe is left
+        assert!(rewind.seconds() <= KEYFRAME_INTERVAL as f32 / TIMER_HZ as f32);
+        assert_eq!(rewind.keyframes.len(), 1);
+    }
+}
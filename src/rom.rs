@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+
+use crate::cpu::MAX_ROM_SIZE;
+
+#[derive(Debug)]
+pub enum RomError {
+    // The ROM file doesn't exist.
+    NotFound(String),
+    // The ROM file has no contents.
+    Empty,
+    // The ROM doesn't fit in the memory between 0x200 and the end of RAM,
+    // `max` depends on the variant it is loaded into.
+    TooLarge { size: usize, max: usize },
+    // Any other error opening or reading the file.
+    Io(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::NotFound(path) => write!(f, "ROM file not found: {}", path),
+            RomError::Empty => write!(f, "ROM file is empty"),
+            RomError::TooLarge { size, max } => write!(f, "ROM is {} bytes but at most {} bytes fit in memory", size, max),
+            RomError::Io(error) => write!(f, "could not read ROM file: {}", error),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RomError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(error: io::Error) -> RomError {
+        RomError::Io(error)
+    }
+}
+
+// A program image. Only the bytes actually in the file are kept, so memory
+// past the end of the ROM isn't mistaken for program data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rom {
+    data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Rom, RomError> {
+        if data.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if data.len() > MAX_ROM_SIZE {
+            return Err(RomError::TooLarge { size: data.len(), max: MAX_ROM_SIZE });
+        }
+        Ok(Rom { data })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    // Always false, empty ROMs are rejected when loading.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Hash of the contents, identifies the ROM in save states and movies.
+    pub fn hash(&self) -> u64 {
+        fnv1a(&self.data)
+    }
+}
+
+// 64 bit FNV-1a hash.
+pub fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3))
+}
+
+pub fn load_rom(fname: &str) -> Result<Rom, RomError> {
+    let mut file_handle = match File::open(fname) {
+        Ok(file) => file,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Err(RomError::NotFound(fname.to_string()));
+        }
+        Err(error) => return Err(RomError::Io(error)),
+    };
+    let mut rom_buffer = Vec::new();
+    file_handle.read_to_end(&mut rom_buffer)?;
+    Rom::from_bytes(rom_buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    use crate::chip8::Chip8;
+    use crate::variant::Variant;
+
+    // A file in the temporary directory that is removed again when dropped.
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path = env::temp_dir().join(format!("rchip8-{}-{}", std::process::id(), name));
+            fs::write(&path, contents).unwrap();
+            TempFile(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn loads_file_keeping_its_length() {
+        let file = TempFile::new("three.ch8", &[0x12, 0x00, 0xFF]);
+        let rom = load_rom(&file.0).unwrap();
+        assert_eq!(rom.len(), 3);
+        assert_eq!(rom.bytes(), &[0x12, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn reports_missing_and_empty_files() {
+        let path = env::temp_dir().join("rchip8-no-such-rom.ch8").to_string_lossy().into_owned();
+        match load_rom(&path) {
+            Err(RomError::NotFound(missing)) => assert_eq!(missing, path),
+            other => panic!("expected NotFound, got {:?}", other),
+        }
+        let file = TempFile::new("empty.ch8", &[]);
+        assert!(matches!(load_rom(&file.0), Err(RomError::Empty)));
+        assert!(matches!(Rom::from_bytes(Vec::new()), Err(RomError::Empty)));
+    }
+
+    #[test]
+    fn rejects_roms_too_large_for_the_variant() {
+        match Rom::from_bytes(vec![0; MAX_ROM_SIZE + 1]) {
+            Err(RomError::TooLarge { size, max }) => assert_eq!((size, max), (MAX_ROM_SIZE + 1, MAX_ROM_SIZE)),
+            other => panic!("expected TooLarge, got {:?}", other),
+        }
+        // 0xE00 bytes fit from 0x200 to the end of 4 KiB, CHIP-8X starts at
+        // 0x300 and XO-CHIP has 64 KiB
+        let largest = |variant: Variant, size: usize| {
+            assert!(Chip8::new(Rom::from_bytes(vec![0; size]).unwrap(), variant).is_ok());
+            match Chip8::new(Rom::from_bytes(vec![0; size + 1]).unwrap(), variant) {
+                Err(RomError::TooLarge { size: found, max }) => assert_eq!((found, max), (size + 1, size)),
+                Err(error) => panic!("expected TooLarge, got {:?}", error),
+                Ok(_) => panic!("{:?} loaded a ROM of {} bytes", variant, size + 1),
+            }
+        };
+        largest(Variant::Chip8, 0xE00);
+        largest(Variant::SuperChip, 0xE00);
+        largest(Variant::Chip8X, 0xD00);
+        largest(Variant::XoChip, 0x10000 - 0x200);
+    }
+}
@@ -0,0 +1,362 @@
+// Save states: the complete machine state in a versioned binary file.
+//
+// A state file starts with a magic string, the format version and a hash of
+// the ROM the state was taken from, followed by the machine itself. Each part
+// of the machine implements `Snapshot` next to its definition, writing its
+// fields in order with `StateWriter` and reading them back with
+// `StateReader`. All numbers are big endian.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use crate::chip8::Chip8;
+use crate::rom::fnv1a;
+
+const MAGIC: &[u8; 8] = b"RCHIP8SS";
+// Increase whenever the layout of any snapshot changes.
+pub const STATE_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum StateError {
+    // The state file couldn't be read or written.
+    Io(io::Error),
+    // The file doesn't start with the save state magic.
+    NotAState,
+    // The file was written by a different version of the format.
+    UnsupportedVersion(u16),
+    // The state was saved while running a different ROM.
+    RomMismatch,
+    // The file ends in the middle of the state.
+    Truncated,
+    // A field holds a value the machine can't be in.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(error) => write!(f, "could not access save state: {}", error),
+            StateError::NotAState => write!(f, "not a save state file"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {} (expected {})", version, STATE_VERSION),
+            StateError::RomMismatch => write!(f, "save state belongs to a different ROM"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StateError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StateError {
+    fn from(error: io::Error) -> StateError {
+        StateError::Io(error)
+    }
+}
+
+// Something that can be written to and read back from a save state.
+pub trait Snapshot: Sized {
+    fn save(&self, out: &mut StateWriter);
+    fn load(input: &mut StateReader) -> Result<Self, StateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_be_bytes());
+    }
+
+    // Bytes whose length is fixed by the format.
+    pub fn raw(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Bytes preceded by their length.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.raw(bytes);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.bytes(value.as_bytes());
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data }
+    }
+
+    // Whether everything has been read.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if len > self.data.len() {
+            return Err(StateError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2)?);
+        Ok(u16::from_be_bytes(bytes))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    // Fill `buf` with the next bytes.
+    pub fn raw(&mut self, buf: &mut [u8]) -> Result<(), StateError> {
+        buf.copy_from_slice(self.take(buf.len())?);
+        Ok(())
+    }
+
+    pub fn bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    pub fn string(&mut self) -> Result<String, StateError> {
+        String::from_utf8(self.bytes()?).map_err(|_| StateError::Invalid("string"))
+    }
+}
+
+// Serialize the machine into a save state.
+pub fn save_state(chip8: &Chip8) -> Vec<u8> {
+    let mut out = StateWriter::new();
+    out.raw(MAGIC);
+    out.u16(STATE_VERSION);
+    out.u64(chip8.rom.hash());
+    chip8.save_machine(&mut out);
+    out.into_bytes()
+}
+
+// Restore the machine from a save state taken while running the same ROM.
+// The machine is only changed if the whole state is valid.
+pub fn load_state(chip8: &mut Chip8, data: &[u8]) -> Result<(), StateError> {
+    let mut input = StateReader::new(data);
+    let mut magic = [0; 8];
+    input.raw(&mut magic).map_err(|_| StateError::NotAState)?;
+    if &magic != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let version = input.u16()?;
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    if input.u64()? != chip8.rom.hash() {
+        return Err(StateError::RomMismatch);
+    }
+    chip8.load_machine(&mut input)
+}
+
+// Hash of the whole machine state, two machines with the same hash will run
+// the same way.
+pub fn state_hash(chip8: &Chip8) -> u64 {
+    fnv1a(&save_state(chip8))
+}
+
+pub fn save_state_file(chip8: &Chip8, path: &str) -> Result<(), StateError> {
+    fs::write(path, save_state(chip8))?;
+    Ok(())
+}
+
+pub fn load_state_file(chip8: &mut Chip8, path: &str) -> Result<(), StateError> {
+    let data = fs::read(path)?;
+    load_state(chip8, &data)
+}
+
+// Run length encoding for keeping states in memory or inside other files,
+// which works well on the mostly empty memory and display of a save state. A
+// control byte below 0x80 is followed by that many plus one literal bytes, one
+// from 0x80 up is followed by a byte repeated `control - 0x80 + MIN_RUN`
+// times.
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 0x7F + MIN_RUN;
+const MAX_LITERALS: usize = 0x80;
+
+pub(crate) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literals = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..].iter().take(MAX_RUN).take_while(|&&byte| byte == data[i]).count();
+        if run >= MIN_RUN {
+            flush_literals(&mut out, &data[i - literals..i]);
+            literals = 0;
+            out.push((0x80 + run - MIN_RUN) as u8);
+            out.push(data[i]);
+            i += run;
+        } else {
+            literals += 1;
+            i += 1;
+            if literals == MAX_LITERALS {
+                flush_literals(&mut out, &data[i - literals..i]);
+                literals = 0;
+            }
+        }
+    }
+    flush_literals(&mut out, &data[data.len() - literals..]);
+    out
+}
+
+fn flush_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    if !literals.is_empty() {
+        out.push((literals.len() - 1) as u8);
+        out.extend_from_slice(literals);
+    }
+}
+
+pub(crate) fn decompress(data: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut out = Vec::new();
+    let mut input = StateReader::new(data);
+    while !input.is_empty() {
+        let control = input.u8()? as usize;
+        if control < 0x80 {
+            out.extend_from_slice(input.take(control + 1)?);
+        } else {
+            let byte = input.u8()?;
+            out.resize(out.len() + control - 0x80 + MIN_RUN, byte);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // Draws random sprites forever, so the state covers memory, registers,
+    // the display and the random number generator.
+    const PROGRAM: [u8; 10] = [0xC0, 0x3F, 0xC1, 0x1F, 0xA0, 0x00, 0xD0, 0x15, 0x12, 0x00];
+
+    fn machine(program: &[u8]) -> Chip8 {
+        Chip8::from_program(program, Variant::XoChip)
+    }
+
+    #[test]
+    fn round_trip_continues_identically() {
+        let mut original = machine(&PROGRAM);
+        original.input.set_key(0xA, true);
+        for _ in 0..5 {
+            original.run_frame().unwrap();
+        }
+        let state = save_state(&original);
+
+        let mut restored = machine(&PROGRAM);
+        load_state(&mut restored, &state).unwrap();
+        assert_eq!(save_state(&restored), state);
+        assert!(restored.input.is_pressed(0xA));
+        for _ in 0..5 {
+            original.run_frame().unwrap();
+            restored.run_frame().unwrap();
+        }
+        assert_eq!(restored.frame_buffer, original.frame_buffer);
+        assert_eq!(restored.cpu.V, original.cpu.V);
+    }
+
+    #[test]
+    fn rejects_other_rom() {
+        let state = save_state(&machine(&PROGRAM));
+        let mut other = machine(&[0x12, 0x00]);
+        assert!(matches!(load_state(&mut other, &state), Err(StateError::RomMismatch)));
+    }
+
+    #[test]
+    fn rejects_other_versions_and_files() {
+        let mut chip8 = machine(&PROGRAM);
+        let mut state = save_state(&chip8);
+        assert!(matches!(load_state(&mut chip8, b"not a state"), Err(StateError::NotAState)));
+        state[MAGIC.len() + 1] += 1;
+        assert!(matches!(load_state(&mut chip8, &state), Err(StateError::UnsupportedVersion(3))));
+    }
+
+    #[test]
+    fn truncated_state_leaves_machine_unchanged() {
+        let mut chip8 = machine(&PROGRAM);
+        let state = save_state(&chip8);
+        chip8.run_frame().unwrap();
+        let before = save_state(&chip8);
+        assert!(matches!(load_state(&mut chip8, &state[..state.len() - 1]), Err(StateError::Truncated)));
+        assert_eq!(save_state(&chip8), before);
+    }
+
+    #[test]
+    fn compression_round_trip() {
+        let mut data = vec![0; 1000];
+        data.extend((0..=255).collect::<Vec<u8>>());
+        data.extend_from_slice(&[7, 7, 1, 7, 7, 7, 2]);
+        let compressed = compress(&data);
+        assert!(compressed.len() < 300);
+        assert_eq!(decompress(&compressed).unwrap(), data);
+        assert_eq!(decompress(&compress(&[])).unwrap(), Vec::<u8>::new());
+        assert!(matches!(decompress(&[0x05, 1, 2]), Err(StateError::Truncated)));
+    }
+}
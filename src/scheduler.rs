@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+use crate::chip8::TIMER_HZ;
+
+// If the host falls further behind than this (e.g. the window was being
+// dragged) the missed frames are dropped instead of being run all at once.
+const MAX_CATCH_UP_FRAMES: u64 = 5;
+
+// Fixed timestep clock for real time frontends. Frames are due at exact
+// multiples of 1/60 s since the scheduler was created, so rounding in the
+// sleep calls never accumulates into drift.
+pub struct FrameScheduler {
+    start: Instant,
+    frames: u64,
+}
+
+impl FrameScheduler {
+    pub fn new() -> FrameScheduler {
+        FrameScheduler {
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    fn frame_time(frame: u64) -> Duration {
+        Duration::from_nanos(frame * 1_000_000_000 / TIMER_HZ as u64)
+    }
+
+    // Number of frames that need to run now to catch up with the wall clock.
+    pub fn frames_due(&mut self) -> u64 {
+        self.frames_due_at(Instant::now())
+    }
+
+    fn frames_due_at(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start).as_nanos() as u64;
+        let target = elapsed * TIMER_HZ as u64 / 1_000_000_000 + 1;
+        if target <= self.frames {
+            return 0;
+        }
+        self.frames = self.frames.max(target - MAX_CATCH_UP_FRAMES.min(target));
+        let due = target - self.frames;
+        self.frames = target;
+        due
+    }
+
+    // How long to sleep until the next frame is due.
+    pub fn until_next_frame(&self) -> Duration {
+        let next = self.start + FrameScheduler::frame_time(self.frames);
+        next.saturating_duration_since(Instant::now())
+    }
+}
+
+impl Default for FrameScheduler {
+    fn default() -> FrameScheduler {
+        FrameScheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catches_up_with_the_clock() {
+        let mut scheduler = FrameScheduler::new();
+        let start = scheduler.start;
+        // The first frame is due straight away, and only once
+        assert_eq!(scheduler.frames_due_at(start), 1);
+        assert_eq!(scheduler.frames_due_at(start), 0);
+        assert_eq!(scheduler.frames_due_at(start + FrameScheduler::frame_time(1) / 2), 0);
+        // Three frame times later the frames missed in between are run
+        assert_eq!(scheduler.frames_due_at(start + FrameScheduler::frame_time(3)), 3);
+        assert_eq!(scheduler.frames, 4);
+    }
+
+    #[test]
+    fn drops_frames_when_far_behind() {
+        let mut scheduler = FrameScheduler::new();
+        let start = scheduler.start;
+        assert_eq!(scheduler.frames_due_at(start), 1);
+        // A second later only the last few frames are run, and the schedule
+        // carries on from there instead of trying to make up the rest
+        assert_eq!(scheduler.frames_due_at(start + Duration::from_secs(1)), MAX_CATCH_UP_FRAMES);
+        assert_eq!(scheduler.frames, TIMER_HZ as u64 + 1);
+        assert_eq!(scheduler.frames_due_at(start + Duration::from_secs(1)), 0);
+        assert_eq!(scheduler.frames_due_at(start + Duration::from_millis(1020)), 1);
+    }
+}
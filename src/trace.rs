@@ -0,0 +1,196 @@
+// Execution traces: one line of text per executed instruction, with the
+// machine state before it ran.
+//
+//     # cycle pc opcode v0-vf i sp dt instruction
+//     0 0200 A22A 00000000000000000000000000000000 0000 00 00 LD I, #22A
+//
+// Every field but the instruction is hex, except the cycle, which counts the
+// instructions executed since tracing started in decimal. V0 to VF are
+// written as one run of 32 digits. Lines are easy to compare against other
+// emulators' logs with diff or a script.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::cpu::{self, CPU};
+use crate::instruction::{Instruction, CLASSES};
+
+pub const HEADER: &str = "# cycle pc opcode v0-vf i sp dt instruction";
+
+// The state of the machine before an instruction executed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u32,
+    pub sp: u8,
+    pub dt: u8,
+    pub instruction: String,
+}
+
+impl TraceRecord {
+    // The record for the instruction at PC, None if PC is outside of memory.
+    pub fn from_cpu(cycle: u64, cpu: &CPU) -> Option<TraceRecord> {
+        let (opcode, instruction, _) = cpu::decode_at(cpu, cpu.PC)?;
+        Some(TraceRecord { cycle, pc: cpu.PC, opcode, v: cpu.V, i: cpu.I, sp: cpu.SP, dt: cpu.DT, instruction: instruction.to_string() })
+    }
+}
+
+impl fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X} {:04X} ", self.cycle, self.pc, self.opcode)?;
+        for v in self.v.iter() {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(f, " {:04X} {:02X} {:02X} {}", self.i, self.sp, self.dt, self.instruction)
+    }
+}
+
+// Which instructions get written to the trace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    // Inclusive range of addresses, everything if None.
+    pub pc: Option<(u16, u16)>,
+    // Instruction classes, see `Instruction::class`; everything if empty.
+    pub classes: Vec<&'static str>,
+}
+
+impl TraceFilter {
+    // Set the address range from `START-END` in hex.
+    pub fn set_pc_range(&mut self, range: &str) -> Result<(), String> {
+        let error = || format!("invalid address range {}, expected START-END in hex", range);
+        let (start, end) = range.split_once('-').ok_or_else(error)?;
+        let parse = |addr: &str| u16::from_str_radix(addr.trim_start_matches("0x").trim_start_matches('#'), 16).map_err(|_| error());
+        let (start, end) = (parse(start)?, parse(end)?);
+        if start > end {
+            return Err(error());
+        }
+        self.pc = Some((start, end));
+        Ok(())
+    }
+
+    // Add classes from a comma separated list.
+    pub fn add_classes(&mut self, list: &str) -> Result<(), String> {
+        for name in list.split(',') {
+            let class = CLASSES.iter().find(|&&class| class == name).ok_or(format!("unknown instruction class {}, expected one of {}", name, CLASSES.join(", ")))?;
+            self.classes.push(class);
+        }
+        Ok(())
+    }
+
+    pub fn matches(&self, pc: u16, instruction: &Instruction) -> bool {
+        self.pc.is_none_or(|(start, end)| (start..=end).contains(&pc)) && (self.classes.is_empty() || self.classes.contains(&instruction.class()))
+    }
+}
+
+// Writes the trace of a running machine, see `Chip8::tracer`.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+    cycle: u64,
+    // The first write that failed, tracing stops there.
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(mut out: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        let error = writeln!(out, "{}", HEADER).err();
+        Tracer { out, filter, cycle: 0, error }
+    }
+
+    // Called before every instruction executes.
+    pub fn record(&mut self, cpu: &CPU) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.error.is_some() {
+            return;
+        }
+        let (_, instruction, _) = match cpu::decode_at(cpu, cpu.PC) {
+            Some(decoded) => decoded,
+            None => return,
+        };
+        if !self.filter.matches(cpu.PC, &instruction) {
+            return;
+        }
+        if let Some(record) = TraceRecord::from_cpu(cycle, cpu) {
+            self.error = writeln!(self.out, "{}", record).err();
+        }
+    }
+
+    // Flush the trace, reporting any write that failed along the way.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::chip8::Chip8;
+    use crate::variant::Variant;
+
+    // Lets the test read what the tracer wrote.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 200: LD V0, 5; 202: ADD V0, 1; 204: LD I, 208; 206: JP 202
+    const PROGRAM: [u8; 8] = [0x60, 0x05, 0x70, 0x01, 0xA2, 0x08, 0x12, 0x02];
+
+    fn trace(filter: TraceFilter, steps: usize) -> Vec<String> {
+        let mut chip8 = Chip8::from_program(&PROGRAM, Variant::Chip8);
+        let out = Shared::default();
+        chip8.tracer = Some(Tracer::new(Box::new(out.clone()), filter));
+        for _ in 0..steps {
+            chip8.step().unwrap();
+        }
+        chip8.tracer.take().unwrap().finish().unwrap();
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn records_state_before_each_instruction() {
+        let lines = trace(TraceFilter::default(), 3);
+        assert_eq!(
+            lines,
+            [
+                HEADER,
+                "0 0200 6005 00000000000000000000000000000000 0000 00 00 LD V0, #05",
+                "1 0202 7001 05000000000000000000000000000000 0000 00 00 ADD V0, #01",
+                "2 0204 A208 06000000000000000000000000000000 0000 00 00 LD I, #208",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_address_and_class() {
+        let mut filter = TraceFilter::default();
+        filter.set_pc_range("202-206").unwrap();
+        filter.add_classes("alu,flow").unwrap();
+        let lines = trace(filter, 8);
+        let cycles: Vec<&str> = lines[1..].iter().map(|line| line.split(' ').next().unwrap()).collect();
+        // ADD and JP but not LD I, and not the LD V0 before the range
+        assert_eq!(cycles, ["1", "3", "4", "6", "7"]);
+        assert!(TraceFilter::default().add_classes("maths").is_err());
+        assert!(TraceFilter::default().set_pc_range("300-200").is_err());
+    }
+}
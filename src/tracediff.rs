@@ -0,0 +1,340 @@
+// Comparing a run against a reference trace, usually from another emulator,
+// to find the first instruction where the two go different ways.
+//
+// The reference uses the `--trace` format (see `trace`). Logs from other
+// emulators need converting to it; fields they don't have can be `*`, as can
+// single registers in the V0-VF field (`**`). A record may end with memory
+// bytes to check, written `[ADDR]=XX` after the instruction text, which is
+// otherwise ignored:
+//
+//     12 0206 D015 0A0C**00000000000000000000000000 0208 00 * DRW V0, V1, 5 [0300]=FF
+//
+// Records are lined up with the run by their cycle, so a filtered trace
+// works as well. A record with `*` as its cycle is for the instruction after
+// the one of the previous record.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::cpu;
+use crate::fault::CpuFault;
+use crate::trace::TraceRecord;
+
+// A record of the reference trace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expected {
+    // Line in the reference, starting from 1.
+    pub line: usize,
+    pub text: String,
+    pub cycle: u64,
+    pc: Option<u16>,
+    opcode: Option<u16>,
+    v: [Option<u8>; 16],
+    i: Option<u32>,
+    sp: Option<u8>,
+    dt: Option<u8>,
+    memory: Vec<(u32, u8)>,
+}
+
+// A hex field, None for `*`.
+fn hex(field: &str, max: u64, name: &str) -> Result<Option<u64>, String> {
+    if field == "*" || field.chars().all(|c| c == '*') {
+        return Ok(None);
+    }
+    match u64::from_str_radix(field, 16) {
+        Ok(value) if value <= max => Ok(Some(value)),
+        _ => Err(format!("invalid {} {}", name, field)),
+    }
+}
+
+fn parse_record(line: usize, text: &str, previous: Option<u64>) -> Result<Expected, String> {
+    let fields: Vec<&str> = text.split_whitespace().collect();
+    if fields.len() < 7 {
+        return Err("expected cycle, pc, opcode, v0-vf, i, sp and dt".to_string());
+    }
+    let cycle = match fields[0] {
+        "*" => previous.map_or(0, |cycle| cycle + 1),
+        field => field.parse().map_err(|_| format!("invalid cycle {}", field))?,
+    };
+    if previous.is_some_and(|previous| cycle <= previous) {
+        return Err(format!("cycle {} doesn't come after the previous record", cycle));
+    }
+    let registers = fields[3];
+    let mut v = [None; 16];
+    if registers != "*" {
+        if registers.len() != 32 || !registers.is_ascii() {
+            return Err(format!("invalid v0-vf {}, expected 32 hex digits", registers));
+        }
+        for (reg, value) in v.iter_mut().enumerate() {
+            *value = hex(&registers[reg * 2..reg * 2 + 2], 0xFF, "v0-vf")?.map(|value| value as u8);
+        }
+    }
+    let mut memory = Vec::new();
+    for field in fields[7..].iter() {
+        let check = field.strip_prefix('[').and_then(|field| field.split_once("]="));
+        if let Some((addr, value)) = check {
+            let addr = hex(addr, 0xFF_FFFF, "address")?.ok_or("memory checks need an address")?;
+            let value = hex(value, 0xFF, "memory byte")?.ok_or("memory checks need a value")?;
+            memory.push((addr as u32, value as u8));
+        }
+    }
+    Ok(Expected {
+        line,
+        text: text.to_string(),
+        cycle,
+        pc: hex(fields[1], 0xFFFF, "pc")?.map(|pc| pc as u16),
+        opcode: hex(fields[2], 0xFFFF, "opcode")?.map(|opcode| opcode as u16),
+        v,
+        i: hex(fields[4], 0xFF_FFFF, "i")?.map(|i| i as u32),
+        sp: hex(fields[5], 0xFF, "sp")?.map(|sp| sp as u8),
+        dt: hex(fields[6], 0xFF, "dt")?.map(|dt| dt as u8),
+        memory,
+    })
+}
+
+// Read a reference trace, skipping blank lines and `#` comments.
+pub fn parse_reference(text: &str) -> Result<Vec<Expected>, String> {
+    let mut records: Vec<Expected> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_text = line.trim();
+        if line_text.is_empty() || line_text.starts_with('#') {
+            continue;
+        }
+        let previous = records.last().map(|record| record.cycle);
+        let record = parse_record(index + 1, line_text, previous).map_err(|message| format!("line {}: {}", index + 1, message))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// A value that isn't what the reference expected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Difference {
+    // PC, OPCODE, V0 to VF, I, SP, DT or [ADDR].
+    pub what: String,
+    pub expected: u32,
+    pub found: u32,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is {:02X}, expected {:02X}", self.what, self.found, self.expected)
+    }
+}
+
+fn differences(expected: &Expected, chip8: &Chip8) -> Vec<Difference> {
+    let cpu = &chip8.cpu;
+    let mut differences = Vec::new();
+    let mut check = |what: String, expected: Option<u32>, found: u32| {
+        if let Some(expected) = expected {
+            if expected != found {
+                differences.push(Difference { what, expected, found });
+            }
+        }
+    };
+    check("PC".to_string(), expected.pc.map(u32::from), cpu.PC as u32);
+    let opcode = cpu::decode_at(cpu, cpu.PC).map_or(0, |(opcode, _, _)| opcode);
+    check("OPCODE".to_string(), expected.opcode.map(u32::from), opcode as u32);
+    for (reg, value) in expected.v.iter().enumerate() {
+        check(format!("V{:X}", reg), value.map(u32::from), cpu.V[reg] as u32);
+    }
+    check("I".to_string(), expected.i, cpu.I);
+    check("SP".to_string(), expected.sp.map(u32::from), cpu.SP as u32);
+    check("DT".to_string(), expected.dt.map(u32::from), cpu.DT as u32);
+    for &(addr, value) in expected.memory.iter() {
+        let found = cpu.memory.get(addr as usize).copied().unwrap_or(0);
+        check(format!("[{:04X}]", addr), Some(value as u32), found as u32);
+    }
+    differences
+}
+
+// Where the run first differs from the reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    // Line of the differing record in the reference.
+    pub line: usize,
+    pub cycle: u64,
+    pub differences: Vec<Difference>,
+    // Reference records around the differing one, marked with its index.
+    pub reference: Vec<String>,
+    pub reference_at: usize,
+    // The run around the differing instruction.
+    pub run: Vec<String>,
+    pub run_at: usize,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "first difference at line {} of the reference, cycle {}:", self.line, self.cycle)?;
+        for difference in self.differences.iter() {
+            writeln!(f, "    {}", difference)?;
+        }
+        for (title, lines, at) in [("reference", &self.reference, self.reference_at), ("rchip8", &self.run, self.run_at)] {
+            writeln!(f, "{}:", title)?;
+            for (index, line) in lines.iter().enumerate() {
+                writeln!(f, "  {} {}", if index == at { '>' } else { ' ' }, line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum Comparison {
+    // The run matched every record.
+    Match,
+    Mismatch(Mismatch),
+    // The program exited before the record at this line.
+    Exited(usize),
+    // The CPU faulted before the record at this line.
+    Fault(usize, CpuFault),
+}
+
+// Run the machine from its current state and compare it against the
+// reference, showing `context` instructions around a difference.
+pub fn compare(chip8: &mut Chip8, reference: &[Expected], context: usize) -> Comparison {
+    let mut cycle = 0;
+    let mut next = 0;
+    // The last few instructions executed, and the one that differed
+    let mut recent = VecDeque::new();
+    let mut found = None;
+    while next < reference.len() {
+        if chip8.has_exited() {
+            return Comparison::Exited(reference[next].line);
+        }
+        let result = chip8.run_frame_with(|chip8| {
+            let record = TraceRecord::from_cpu(cycle, &chip8.cpu).map(|record| record.to_string()).unwrap_or_default();
+            if reference[next].cycle == cycle {
+                let differences = differences(&reference[next], chip8);
+                if !differences.is_empty() {
+                    found = Some((record, differences));
+                    return false;
+                }
+                next += 1;
+                if next == reference.len() {
+                    return false;
+                }
+            }
+            recent.push_back(record);
+            if recent.len() > context {
+                recent.pop_front();
+            }
+            cycle += 1;
+            true
+        });
+        if let Err(fault) = result {
+            return Comparison::Fault(reference[next].line, fault);
+        }
+        if found.is_some() {
+            break;
+        }
+    }
+    let (record, differences) = match found {
+        Some(found) => found,
+        None => return Comparison::Match,
+    };
+
+    // What happened next, for context
+    let run_at = recent.len();
+    let mut run: Vec<String> = recent.into_iter().collect();
+    run.push(record);
+    for _ in 0..context {
+        if chip8.has_exited() || chip8.step().is_err() {
+            break;
+        }
+        cycle += 1;
+        match TraceRecord::from_cpu(cycle, &chip8.cpu) {
+            Some(record) => run.push(record.to_string()),
+            None => break,
+        }
+    }
+    let first = next.saturating_sub(context);
+    let last = (next + context + 1).min(reference.len());
+    Comparison::Mismatch(Mismatch {
+        line: reference[next].line,
+        cycle: reference[next].cycle,
+        differences,
+        reference: reference[first..last].iter().map(|record| record.text.clone()).collect(),
+        reference_at: next - first,
+        run,
+        run_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // 200: LD V0, 5; 202: ADD V0, 1; 204: LD [I], V0; 206: JP 202
+    const PROGRAM: [u8; 8] = [0x60, 0x05, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02];
+
+    fn machine() -> Chip8 {
+        Chip8::from_program(&PROGRAM, Variant::Chip8)
+    }
+
+    fn line(cycle: &str, pc: u16, opcode: u16, v0: u8) -> String {
+        format!("{} {:04X} {:04X} {:02X}{} 0000 00 00", cycle, pc, opcode, v0, "00".repeat(15))
+    }
+
+    // The first ten instructions of the program.
+    fn reference() -> Vec<String> {
+        let mut lines = vec![line("0", 0x200, 0x6005, 0)];
+        for (round, cycle) in (1..10).step_by(3).enumerate() {
+            let v0 = 5 + round as u8;
+            lines.push(line(&cycle.to_string(), 0x202, 0x7001, v0));
+            lines.push(line(&(cycle + 1).to_string(), 0x204, 0xF055, v0 + 1));
+            lines.push(line(&(cycle + 2).to_string(), 0x206, 0x1202, v0 + 1));
+        }
+        lines
+    }
+
+    fn compare_lines(lines: &[String]) -> Comparison {
+        let reference = parse_reference(&lines.join("\n")).unwrap();
+        compare(&mut machine(), &reference, 2)
+    }
+
+    #[test]
+    fn matching_trace() {
+        assert!(matches!(compare_lines(&reference()), Comparison::Match));
+        // Filtered traces and wildcards
+        let sparse = vec![reference()[5].clone(), "* 0206 * * * * * JP #202".to_string(), "9 * * * * * * [0000]=08".to_string()];
+        assert!(matches!(compare_lines(&sparse), Comparison::Match));
+    }
+
+    #[test]
+    fn reports_first_difference() {
+        let mut lines = reference();
+        lines[5] = line("5", 0x204, 0xF055, 0x09);
+        lines.push("# comment".to_string());
+        let mismatch = match compare_lines(&lines) {
+            Comparison::Mismatch(mismatch) => mismatch,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!((mismatch.line, mismatch.cycle), (6, 5));
+        assert_eq!(mismatch.differences, [Difference { what: "V0".to_string(), expected: 9, found: 7 }]);
+        assert_eq!(mismatch.reference, lines[3..8]);
+        assert_eq!((mismatch.reference_at, mismatch.run_at), (2, 2));
+        // The run has the instruction text as well
+        assert!(mismatch.run.iter().zip(reference()[3..6].iter()).all(|(run, expected)| run.starts_with(expected.as_str())));
+        assert_eq!(mismatch.run.len(), 5);
+        assert!(mismatch.to_string().contains("    V0 is 07, expected 09\n"));
+    }
+
+    #[test]
+    fn reports_memory_differences() {
+        let lines = ["3 0206 1202 * * * * [0000]=07".to_string()];
+        match compare_lines(&lines) {
+            Comparison::Mismatch(mismatch) => assert_eq!(mismatch.differences[0].to_string(), "[0000] is 06, expected 07"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_references() {
+        assert_eq!(parse_reference("0 0200 6005").unwrap_err(), "line 1: expected cycle, pc, opcode, v0-vf, i, sp and dt");
+        assert_eq!(parse_reference("3 * * * * * *\n2 * * * * * *").unwrap_err(), "line 2: cycle 2 doesn't come after the previous record");
+        assert_eq!(parse_reference("0 * * 0102 * * *").unwrap_err(), "line 1: invalid v0-vf 0102, expected 32 hex digits");
+    }
+}
@@ -0,0 +1,123 @@
+use crate::cpu::{MEGA_MEMORY_SIZE, MEMORY_SIZE, PROGRAM_START, XO_MEMORY_SIZE};
+use crate::display::{HEIGHT, WIDTH};
+use crate::quirks::Quirks;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+// The instruction set the machine implements. SUPER-CHIP and its successors
+// only add opcodes that are unused (or no-ops) on the original CHIP-8, so a
+// ROM for an older variant also runs on a newer one. The VIP variants
+// replace some opcodes instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    // The original COSMAC VIP instruction set.
+    #[default]
+    Chip8,
+    // The two page VIP interpreter with a 64x64 display, where 0230 clears
+    // the screen.
+    Chip8Hires,
+    // CHIP-8X for the VP-590 colour board: programs start at 0x300, BxyN
+    // sets colour zones, 02A0 cycles the background colour and a second
+    // keypad is available.
+    Chip8X,
+    // SUPER-CHIP 1.1: 128x64 high resolution mode, scrolling, 16x16 sprites,
+    // a large font and the RPL user flags.
+    SuperChip,
+    // XO-CHIP: SUPER-CHIP plus 64 KiB of memory, two bit planes, audio
+    // patterns and a few more register and scrolling instructions.
+    XoChip,
+    // MEGACHIP: SUPER-CHIP plus a 24 bit address space and a 256x192 mode
+    // with a 256 colour palette, blended sprites and sample playback.
+    MegaChip,
+}
+
+// Names accepted by `Variant::from_name`.
+pub const VARIANTS: [&str; 6] = ["chip8", "chip8-hires", "chip8x", "schip", "xochip", "megachip"];
+
+impl Variant {
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "chip8" => Some(Variant::Chip8),
+            "chip8-hires" => Some(Variant::Chip8Hires),
+            "chip8x" => Some(Variant::Chip8X),
+            "schip" => Some(Variant::SuperChip),
+            "xochip" => Some(Variant::XoChip),
+            "megachip" => Some(Variant::MegaChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Chip8 => "chip8",
+            Variant::Chip8Hires => "chip8-hires",
+            Variant::Chip8X => "chip8x",
+            Variant::SuperChip => "schip",
+            Variant::XoChip => "xochip",
+            Variant::MegaChip => "megachip",
+        }
+    }
+
+    // The quirks ROMs written for this variant usually expect.
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Variant::Chip8 => Quirks::default(),
+            // Both only ever ran on the VIP
+            Variant::Chip8Hires | Variant::Chip8X => Quirks::vip(),
+            Variant::SuperChip | Variant::MegaChip => Quirks::schip(),
+            Variant::XoChip => Quirks::xochip(),
+        }
+    }
+
+    // Size of the address space in bytes.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Variant::XoChip => XO_MEMORY_SIZE,
+            Variant::MegaChip => MEGA_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    // Where programs are loaded and start running.
+    pub fn program_start(&self) -> usize {
+        match self {
+            Variant::Chip8X => 0x300,
+            _ => PROGRAM_START,
+        }
+    }
+
+    // Display size in pixels when the machine starts.
+    pub fn display_size(&self) -> (usize, usize) {
+        match self {
+            Variant::Chip8Hires => (WIDTH, 2 * HEIGHT),
+            _ => (WIDTH, HEIGHT),
+        }
+    }
+
+    // I wraps around within this mask.
+    pub fn address_mask(&self) -> u32 {
+        match self {
+            Variant::MegaChip => 0xFF_FFFF,
+            _ => 0xFFFF,
+        }
+    }
+
+    // Whether the SUPER-CHIP instructions are available.
+    pub fn has_schip(&self) -> bool {
+        matches!(self, Variant::SuperChip | Variant::XoChip | Variant::MegaChip)
+    }
+
+    // Whether the XO-CHIP instructions are available.
+    pub fn has_xo(&self) -> bool {
+        *self == Variant::XoChip
+    }
+}
+
+impl Snapshot for Variant {
+    fn save(&self, out: &mut StateWriter) {
+        out.str(self.name());
+    }
+
+    fn load(input: &mut StateReader) -> Result<Variant, StateError> {
+        Variant::from_name(&input.string()?).ok_or(StateError::Invalid("variant"))
+    }
+}
@@ -0,0 +1,186 @@
+// Conformance tests: run test ROMs without a window for a fixed number of
+// frames and compare the display with a golden image in the format of
+// `FrameBuffer::to_text`.
+//
+// The ROMs in tests/roms/*.asm are assembled first. They check themselves
+// and draw the result, a tick or cross per instruction or flag test and a
+// digit per quirk, so their golden images were checked by reading them, not
+// against another emulator.
+//
+// The Timendus CHIP-8 test suite (https://github.com/Timendus/chip8-test-suite)
+// isn't part of the repository. Put its ROMs and golden images taken from a
+// reference emulator in tests/roms/timendus/ and tests/golden/ and run
+//
+//     cargo test --test conformance -- --ignored
+//
+// Missing ROMs fail the test.
+//
+// To accept changed output, once it has been checked to be right, run
+//
+//     RCHIP8_BLESS=1 cargo test --test conformance
+//
+// which writes the golden images in tests/golden/ instead of comparing.
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::PathBuf;
+
+use rchip8::asm::assemble_file;
+use rchip8::quirks::Quirks;
+use rchip8::random::{Random, RandomMode};
+use rchip8::variant::Variant;
+use rchip8::{load_rom, Chip8, Rom};
+
+struct Case {
+    // Name of the golden image in tests/golden/.
+    name: &'static str,
+    // Path in tests/roms/, `.asm` files are assembled.
+    rom: &'static str,
+    variant: &'static str,
+    // Quirks preset, the variant's own if None.
+    quirks: Option<&'static str>,
+    frames: u32,
+    // Stored at 0x1FF before running, the Timendus ROMs read it to pick a
+    // test without showing their menu.
+    select: Option<u8>,
+    // Keys held down: key, first frame, last frame.
+    keys: &'static [(u8, u32, u32)],
+}
+
+const fn case(name: &'static str, rom: &'static str, variant: &'static str, frames: u32) -> Case {
+    Case { name, rom, variant, quirks: None, frames, select: None, keys: &[] }
+}
+
+const fn with_quirks(case: Case, quirks: &'static str) -> Case {
+    Case { quirks: Some(quirks), ..case }
+}
+
+const fn with_select(case: Case, select: u8) -> Case {
+    Case { select: Some(select), ..case }
+}
+
+const BUILT_IN: [Case; 8] = [
+    // The instructions and flags don't depend on the quirks
+    with_quirks(case("opcodes", "opcodes.asm", "chip8", 60), "vip"),
+    with_quirks(case("flags", "flags.asm", "chip8", 60), "vip"),
+    with_quirks(case("quirks-vip", "quirks.asm", "chip8", 30), "vip"),
+    with_quirks(case("quirks-chip48", "quirks.asm", "chip8", 30), "chip48"),
+    with_quirks(case("quirks-schip", "quirks.asm", "chip8", 30), "schip"),
+    with_quirks(case("quirks-xochip", "quirks.asm", "chip8", 30), "xochip"),
+    with_quirks(case("quirks-modern", "quirks.asm", "chip8", 30), "modern"),
+    Case { keys: &[(0xA, 5, 10), (0xB, 20, 30)], ..case("keypad", "keypad.asm", "chip8", 40) },
+];
+
+const TIMENDUS: [Case; 10] = [
+    case("timendus-chip8-logo", "timendus/1-chip8-logo.ch8", "chip8", 60),
+    case("timendus-ibm-logo", "timendus/2-ibm-logo.ch8", "chip8", 60),
+    case("timendus-corax", "timendus/3-corax+.ch8", "chip8", 60),
+    case("timendus-flags", "timendus/4-flags.ch8", "chip8", 120),
+    // 1 is the CHIP-8 test, 2 SUPER-CHIP and 3 XO-CHIP
+    with_quirks(with_select(case("timendus-quirks-chip8", "timendus/5-quirks.ch8", "chip8", 300), 1), "vip"),
+    with_select(case("timendus-quirks-schip", "timendus/5-quirks.ch8", "schip", 300), 2),
+    with_select(case("timendus-quirks-xochip", "timendus/5-quirks.ch8", "xochip", 300), 3),
+    // 3 is the Fx0A test, which waits for a key to be pressed and released
+    Case { keys: &[(0x5, 30, 40)], ..with_select(case("timendus-keypad", "timendus/6-keypad.ch8", "chip8", 90), 3) },
+    with_select(case("timendus-scrolling-lores", "timendus/8-scrolling.ch8", "schip", 120), 1),
+    with_select(case("timendus-scrolling-hires", "timendus/8-scrolling.ch8", "schip", 120), 2),
+];
+
+fn tests_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+fn load(case: &Case, variant: Variant) -> Result<Rom, String> {
+    let name = tests_dir().join("roms").join(case.rom).to_string_lossy().into_owned();
+    if case.rom.ends_with(".asm") {
+        let assembly = assemble_file(&name, variant).map_err(|error| error.to_string())?;
+        return Rom::from_bytes(assembly.rom).map_err(|error| error.to_string());
+    }
+    load_rom(&name).map_err(|error| error.to_string())
+}
+
+// The display after running the case.
+fn run(case: &Case) -> Result<String, String> {
+    let variant = Variant::from_name(case.variant).unwrap();
+    let rom = load(case, variant)?;
+    let mut chip8 = Chip8::new(rom, variant).map_err(|error| error.to_string())?;
+    chip8.cpu.quirks = case.quirks.map_or_else(|| variant.default_quirks(), |name| Quirks::preset(name).unwrap());
+    chip8.cpu.rng = Random::new(0, RandomMode::Splitmix);
+    if let Some(select) = case.select {
+        chip8.cpu.memory[0x1FF] = select;
+    }
+    for frame in 0..case.frames {
+        for &(key, first, last) in case.keys {
+            chip8.input.set_key(key, (first..=last).contains(&frame));
+        }
+        if chip8.has_exited() {
+            break;
+        }
+        chip8.run_frame().map_err(|fault| format!("CPU fault in frame {}: {}", frame, fault))?;
+    }
+    Ok(chip8.frame_buffer.to_text())
+}
+
+// The rows that differ, with the differing pixels marked.
+fn diff(expected: &str, found: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let found: Vec<&str> = found.lines().collect();
+    let size = |lines: &[&str]| (lines.first().map_or(0, |line| line.len()), lines.len());
+    if size(&expected) != size(&found) {
+        let ((width, height), (found_width, found_height)) = (size(&expected), size(&found));
+        return format!("display is {}x{}, expected {}x{}\n{}\n", found_width, found_height, width, height, found.join("\n"));
+    }
+    let mut out = String::new();
+    for (row, (expected, found)) in expected.iter().zip(found.iter()).enumerate() {
+        if expected != found {
+            let marks: String = expected.chars().zip(found.chars()).map(|(a, b)| if a == b { ' ' } else { '^' }).collect();
+            let _ = writeln!(out, "row {:2} expected {}", row, expected);
+            let _ = writeln!(out, "       found    {}", found);
+            let _ = writeln!(out, "                {}", marks);
+        }
+    }
+    out
+}
+
+fn check(cases: &[Case]) {
+    let bless = env::var_os("RCHIP8_BLESS").is_some();
+    let mut failures = Vec::new();
+    for case in cases {
+        let found = match run(case) {
+            Ok(found) => found,
+            Err(message) => {
+                failures.push(format!("{}: {}", case.name, message));
+                continue;
+            }
+        };
+        let golden = tests_dir().join("golden").join(format!("{}.txt", case.name));
+        if bless {
+            fs::write(&golden, &found).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&golden) {
+            Ok(expected) if expected == found => {}
+            Ok(expected) => failures.push(format!("{} differs from {}:\n{}", case.name, golden.display(), diff(&expected, &found))),
+            Err(_) => failures.push(format!("{} has no golden image, run with RCHIP8_BLESS=1 once this is right:\n{}", case.name, found)),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn built_in_roms() {
+    check(&BUILT_IN);
+}
+
+#[test]
+#[ignore = "needs the Timendus ROMs in tests/roms/timendus"]
+fn timendus_suite() {
+    check(&TIMENDUS);
+}
+
+#[test]
+fn diff_marks_changed_pixels() {
+    assert_eq!(diff("..\n##\n", "..\n#.\n"), "row  1 expected ##\n       found    #.\n                 ^\n");
+    assert!(diff("..\n", "...\n").starts_with("display is 3x1, expected 2x1"));
+}
@@ -0,0 +1,19 @@
+; Shows the key LD V0, K returns, then waits for key B to be held and shows
+; it next to it.
+
+    LD V0, K
+    LD F, V0
+    LD V1, 0
+    LD V2, 0
+    DRW V1, V2, 5
+
+    LD V3, #B
+wait:
+    SKP V3
+    JP wait
+    LD F, V3
+    LD V1, 6
+    DRW V1, V2, 5
+
+end:
+    JP end
@@ -0,0 +1,270 @@
+; Every CHIP-8 instruction that can be checked without a key press, in the
+; spirit of corax+. Each check leaves a result in V1 and the expected value
+; in V3 and draws a tick when they agree and a cross otherwise, ten to a row,
+; at the position in V8 and V9. The 8xyN flags are in flags.asm, Fx0A in
+; keypad.asm and the quirks in quirks.asm.
+; Only behaviour all the quirk presets agree on is checked.
+
+    ; 00E0 CLS: a sprite drawn after clearing doesn't collide
+    LD V0, 58
+    LD V2, 26
+    LD I, block
+    DRW V0, V2, 4
+    CLS
+    DRW V0, V2, 4
+    LD VA, VF
+    CLS
+    LD V8, 0
+    LD V9, 0
+    LD V1, VA
+    LD V3, 0
+    CALL check
+
+    ; Dxyn: no collision on an empty screen, a collision when drawn again,
+    ; which also erases the sprite
+    LD I, block
+    DRW V0, V2, 4
+    LD V1, VF
+    LD V3, 0
+    CALL check
+    LD I, block
+    DRW V0, V2, 4
+    LD V1, VF
+    LD V3, 1
+    CALL check
+
+    ; 1nnn JP
+    LD V1, 1
+    JP jumped
+    LD V1, 0
+jumped:
+    LD V3, 1
+    CALL check
+
+    ; 2nnn CALL and 00EE RET, two levels deep
+    LD V1, 0
+    CALL outer
+    LD V3, 2
+    CALL check
+
+    ; 3xkk SE
+    LD V0, 5
+    LD V1, 1
+    SE V0, 5
+    LD V1, 0
+    LD V3, 1
+    CALL check
+    LD V1, 1
+    SE V0, 6
+    LD V1, 0
+    LD V3, 0
+    CALL check
+
+    ; 4xkk SNE
+    LD V1, 1
+    SNE V0, 6
+    LD V1, 0
+    LD V3, 1
+    CALL check
+    LD V1, 1
+    SNE V0, 5
+    LD V1, 0
+    LD V3, 0
+    CALL check
+
+    ; 5xy0 SE
+    LD V2, 5
+    LD V1, 1
+    SE V0, V2
+    LD V1, 0
+    LD V3, 1
+    CALL check
+    LD V2, 6
+    LD V1, 1
+    SE V0, V2
+    LD V1, 0
+    LD V3, 0
+    CALL check
+
+    ; 9xy0 SNE
+    LD V1, 1
+    SNE V0, V2
+    LD V1, 0
+    LD V3, 1
+    CALL check
+    LD V2, 5
+    LD V1, 1
+    SNE V0, V2
+    LD V1, 0
+    LD V3, 0
+    CALL check
+
+    ; 6xkk LD and 7xkk ADD, which wraps around
+    LD V1, #A5
+    LD V3, #A5
+    CALL check
+    LD V1, #F0
+    ADD V1, #20
+    LD V3, #10
+    CALL check
+
+    ; 8xy0 LD, 8xy1 OR, 8xy2 AND, 8xy3 XOR
+    LD V2, #3C
+    LD V1, V2
+    LD V3, #3C
+    CALL check
+    LD V1, #0F
+    OR V1, V2
+    LD V3, #3F
+    CALL check
+    LD V1, #0F
+    AND V1, V2
+    LD V3, #0C
+    CALL check
+    LD V1, #0F
+    XOR V1, V2
+    LD V3, #33
+    CALL check
+
+    ; Bnnn JP V0: with the jump quirk the offset comes from the register
+    ; named by the address' top nibble instead, so all of them hold 2
+    LD V0, 2
+    LD V2, 2
+    LD V3, 2
+    LD V1, 0
+    JP V0, offset - 2
+    LD V1, 0
+    JP offset_done
+offset:
+    LD V1, 1
+offset_done:
+    LD V3, 1
+    CALL check
+
+    ; Cxkk RND is masked
+    RND V1, 0
+    LD V3, 0
+    CALL check
+    RND V1, #0F
+    LD V2, #F0
+    AND V1, V2
+    LD V3, 0
+    CALL check
+
+    ; Ex9E SKP and ExA1 SKNP with no key pressed
+    LD V0, 5
+    LD V1, 1
+    SKP V0
+    LD V1, 0
+    LD V3, 0
+    CALL check
+    LD V1, 1
+    SKNP V0
+    LD V1, 0
+    LD V3, 1
+    CALL check
+
+    ; Fx15 and Fx07: the delay timer reads back and counts down to zero
+    LD V0, 3
+    LD DT, V0
+    LD V2, DT
+wait:
+    LD V0, DT
+    SE V0, 0
+    JP wait
+    LD V1, 0
+    SE V2, 0
+    LD V1, 1
+    LD V3, 1
+    CALL check
+
+    ; Annn and Fx1E ADD I
+    LD I, data
+    LD V0, 2
+    ADD I, V0
+    LD V0, [I]
+    LD V1, V0
+    LD V3, 3
+    CALL check
+
+    ; Fx29 points at the font, the top row of A is #F0
+    LD V0, #A
+    LD F, V0
+    LD V0, [I]
+    LD V1, V0
+    LD V3, #F0
+    CALL check
+
+    ; Fx33 BCD of 234
+    LD V0, 234
+    LD I, scratch
+    LD B, V0
+    LD I, scratch
+    LD V2, [I]
+    LD V4, 0
+    SE V0, 2
+    JP bcd_done
+    SE V1, 3
+    JP bcd_done
+    SE V2, 4
+    JP bcd_done
+    LD V4, 1
+bcd_done:
+    LD V1, V4
+    LD V3, 1
+    CALL check
+
+    ; Fx55 and Fx65 store and load V0 to Vx, and nothing past Vx
+    LD V0, #11
+    LD V1, #22
+    LD V2, #33
+    LD I, scratch
+    LD [I], V2
+    LD V0, 0
+    LD V1, 0
+    LD V2, 0
+    LD I, scratch
+    LD V1, [I]
+    LD V3, #22
+    CALL check
+    LD V1, V2
+    LD V3, 0
+    CALL check
+
+end:
+    JP end
+
+outer:
+    ADD V1, 1
+    CALL inner
+    RET
+
+inner:
+    ADD V1, 1
+    RET
+
+; Compare V1 against V3, then draw the outcome.
+check:
+    LD I, cross
+    SE V1, V3
+    JP draw
+    LD I, tick
+draw:
+    DRW V8, V9, 5
+    ADD V8, 6
+    SE V8, 60
+    RET
+    LD V8, 0
+    ADD V9, 6
+    RET
+
+tick:
+    db %00001000, %00010000, %10100000, %01000000, %00000000
+cross:
+    db %10001000, %01010000, %00100000, %01010000, %10001000
+block:
+    db #F0, #F0, #F0, #F0
+data:
+    db 1, 2, 3, 4
+scratch:
+    db 0, 0, 0, 0
@@ -0,0 +1,66 @@
+; Shows what the quirks do, one hex digit each from left to right:
+;   vf-reset  VF after OR: 0 reset, 5 left alone
+;   shift     SHR V1, V2 with V1 = 1 and V2 = 4: 2 shifts VY, 0 shifts VX
+;   memory    what LD V0, [I] reads after LD [I], V1: 1 I unchanged,
+;             2 I increased by X, 9 I increased by X + 1
+;   jump      which register JP V0 adds: 1 V0, 2 VX
+; Below them a block is drawn over the right edge, it wraps around to the
+; left edge unless sprites are clipped.
+
+    LD V0, 0
+    LD V2, 2
+    ; Bxnn with x = 2, as long as landing is at 2xx
+    JP V0, landing
+landing:
+    JP added_v0
+    LD VA, 2
+    JP vf_reset
+added_v0:
+    LD VA, 1
+
+vf_reset:
+    LD VF, 5
+    LD V1, 1
+    OR V1, V1
+    LD V6, VF
+
+shift:
+    LD V1, 1
+    LD V2, 4
+    SHR V1, V2
+    LD V7, V1
+
+memory:
+    LD I, buffer
+    LD V0, 1
+    LD V1, 2
+    LD [I], V1
+    LD V0, [I]
+    LD V8, V0
+
+    LD VB, 0
+    LD VC, 0
+    LD F, V6
+    DRW VB, VC, 5
+    ADD VB, 6
+    LD F, V7
+    DRW VB, VC, 5
+    ADD VB, 6
+    LD F, V8
+    DRW VB, VC, 5
+    ADD VB, 6
+    LD F, VA
+    DRW VB, VC, 5
+
+    LD VB, 60
+    LD VC, 10
+    LD I, block
+    DRW VB, VC, 4
+
+end:
+    JP end
+
+buffer:
+    db 7, 8, 9
+block:
+    db #FF, #FF, #FF, #FF